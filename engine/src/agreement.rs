@@ -0,0 +1,179 @@
+/**
+ * Grammatical agreement over a whole ordered expression. The tagging stats only see adjacent pairs of words, this
+ * checks the agreements spanning several words:
+ * - gender and number between a noun and its determiner and adjectives, adverbs and other adjectives in between
+ *   ("le très beau lit")
+ * - person and number between a verb and its subject, object pronouns and adverbs in between ("il ne le lit")
+ *
+ * The words are checked with the readings picked for them, see `scorer::best_readings`. A reading agrees with
+ * another if one of its morphologies agrees with one of the other's. Missing features agree with anything, so that
+ * words tagged without morphology never cause a violation.
+ */
+use crate::{Index, Morph, Person, PosTag, ReadingId};
+use serde_derive::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub(crate) enum AgreementKind {
+    /** Gender and number, between a noun and a determiner or adjective */
+    NounPhrase,
+    /** Person and number, between a verb and its subject */
+    SubjectVerb,
+}
+
+/** Two words of an expression that should agree and do not, as positions in the expression */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub(crate) struct Violation {
+    pub(crate) kind: AgreementKind,
+    pub(crate) first: usize,
+    pub(crate) second: usize,
+}
+
+fn features_agree<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+    a.is_none() || b.is_none() || a == b
+}
+
+fn gender_number_agree(a: &Morph, b: &Morph) -> bool {
+    features_agree(a.gender, b.gender) && features_agree(a.number, b.number)
+}
+
+fn person_number_agree(subject: &Morph, verb: &Morph) -> bool {
+    features_agree(subject.person, verb.person) && features_agree(subject.number, verb.number)
+}
+
+/** A noun subject is in the third person, whatever the morphology says */
+fn noun_subject_agrees(noun: &Morph, verb: &Morph) -> bool {
+    features_agree(Some(Person::Three), verb.person) && features_agree(noun.number, verb.number)
+}
+
+fn readings_agree(index: &Index, first: ReadingId, second: ReadingId, agree: fn(&Morph, &Morph) -> bool) -> bool {
+    let first_morphs = index.reading_morphs(first);
+    let second_morphs = index.reading_morphs(second);
+    if first_morphs.is_empty() || second_morphs.is_empty() {
+        return true;
+    }
+    first_morphs.iter().any(|a| second_morphs.iter().any(|b| agree(a, b)))
+}
+
+/** Every agreement violation of the expression, given as the readings of its words, in order of the second word */
+pub(crate) fn violations(readings: &[ReadingId], index: &Index) -> Vec<Violation> {
+    (0..readings.len()).flat_map(|last| last_word_violations(&readings[..=last], index)).collect()
+}
+
+/**
+ * The agreement violations between the last word of the expression and the previous ones, the second word of a
+ * violation being always the last one: whether a word agrees does not depend on the next words
+ */
+pub(crate) fn last_word_violations(readings: &[ReadingId], index: &Index) -> Vec<Violation> {
+    let pos = |i: usize| index.reading_pos(readings[i]);
+    let i = readings.len() - 1;
+    let mut violations = vec![];
+    let mut check = |first: usize, kind: AgreementKind, agree: fn(&Morph, &Morph) -> bool| {
+        if !readings_agree(index, readings[first], readings[i], agree) {
+            violations.push(Violation { kind, first, second: i });
+        }
+    };
+    match pos(i) {
+        PosTag::NOUN => {
+            /* Determiner and adjectives before the noun */
+            let mut j = i;
+            while j > 0 && matches!(pos(j - 1), PosTag::ADJ | PosTag::ADV) {
+                j -= 1;
+                if pos(j) == PosTag::ADJ {
+                    check(j, AgreementKind::NounPhrase, gender_number_agree);
+                }
+            }
+            if j > 0 && pos(j - 1) == PosTag::DET {
+                check(j - 1, AgreementKind::NounPhrase, gender_number_agree);
+            }
+        }
+        PosTag::ADJ => {
+            /* Adjective after the noun, adverbs and other adjectives in between */
+            let mut j = i;
+            while j > 0 && matches!(pos(j - 1), PosTag::ADJ | PosTag::ADV) {
+                j -= 1;
+            }
+            if j > 0 && pos(j - 1) == PosTag::NOUN {
+                check(j - 1, AgreementKind::NounPhrase, gender_number_agree);
+            }
+        }
+        PosTag::VERB | PosTag::AUX => {
+            /* The subject is the first of the pronouns before the verb, the others being objects */
+            let mut j = i;
+            let mut subject = None;
+            while j > 0 && matches!(pos(j - 1), PosTag::PRON | PosTag::ADV) {
+                j -= 1;
+                if pos(j) == PosTag::PRON {
+                    subject = Some(j);
+                }
+            }
+            match subject {
+                Some(subject) => check(subject, AgreementKind::SubjectVerb, person_number_agree),
+                None if j > 0 && pos(j - 1) == PosTag::NOUN => {
+                    check(j - 1, AgreementKind::SubjectVerb, noun_subject_agrees)
+                }
+                None => {}
+            }
+        }
+        _ => {}
+    }
+    violations
+}
+
+pub(crate) fn is_grammatical(readings: &[ReadingId], index: &Index) -> bool {
+    violations(readings, index).is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{test_index, word_id};
+    use crate::{Gender, Number};
+
+    /** Main readings of the words of the expression */
+    fn words(index: &Index, expression: &str) -> Vec<ReadingId> {
+        expression
+            .split(' ')
+            .map(|w| word_id(index, w))
+            .map(|w| index.word_readings(w)[0])
+            .collect()
+    }
+
+    #[test]
+    fn agreement_violations() {
+        let mut index = test_index();
+        let fem_plur = Morph {
+            gender: Some(Gender::Fem),
+            number: Some(Number::Plur),
+            ..Morph::default()
+        };
+        let third_plur = Morph {
+            number: Some(Number::Plur),
+            person: Some(Person::Three),
+            ..Morph::default()
+        };
+        index.push_word("une", PosTag::DET, vec![Morph { number: Some(Number::Sing), ..fem_plur }], 0.0);
+        index.push_word("pas", PosTag::ADV, vec![], 0.0);
+        index.push_word("lient", PosTag::VERB, vec![third_plur], 0.0);
+        index.push_word("nulles", PosTag::ADJ, vec![fem_plur], 0.0);
+        index.finalize();
+
+        assert!(is_grammatical(&words(&index, "le lit nul"), &index));
+        assert!(is_grammatical(&words(&index, "il pas lie"), &index));
+        assert!(is_grammatical(&words(&index, "le lit lie un lit"), &index));
+        assert_eq!(
+            violations(&words(&index, "une tel pas nul lit nulles"), &index),
+            vec![
+                Violation { kind: AgreementKind::NounPhrase, first: 0, second: 4 },
+                Violation { kind: AgreementKind::NounPhrase, first: 4, second: 5 },
+            ]
+        );
+        assert_eq!(
+            violations(&words(&index, "il pas lient"), &index),
+            vec![Violation { kind: AgreementKind::SubjectVerb, first: 0, second: 2 }]
+        );
+        assert_eq!(
+            violations(&words(&index, "un lit lient"), &index),
+            vec![Violation { kind: AgreementKind::SubjectVerb, first: 1, second: 2 }]
+        );
+    }
+}
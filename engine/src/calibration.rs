@@ -0,0 +1,118 @@
+/**
+ * Normalisation of the raw scores, which are unbounded products of square-rooted counts, to [0, 1]. When the index is
+ * built, the anagrams of `CALIBRATION_QUERIES` are searched with the default options and the quantiles of the scores of
+ * their `CALIBRATION_TOP` best anagrams are kept: most of the anagrams of a query are nonsense nobody browses. The
+ * normalised score of an anagram is then the share of these scores below its own, interpolated between the quantiles
+ * on a log scale, so that it can be compared across queries.
+ *
+ * Single words have no pair of words to score: they get the best score of the calibration, and so a normalised
+ * score of 1 before the factors that do not depend on the ordering (reuse of the input, accents).
+ *
+ * Each scorer of `SCORERS` has its own calibration, as their scores are not on the same scale. The queries are searched
+ * with the default options otherwise, the truncation of the words being seeded by `CALIBRATION_SEED` so that an index
+ * built twice from the same files is calibrated the same. The factors of the query that the default options do not
+ * apply (meaning, accent bonus, other commonness weights or reuse penalties) are out of its scope: the scores they raise
+ * above the best calibrated one are normalised to 1.
+ */
+use serde_derive::{Deserialize, Serialize};
+
+/** Queries of various lengths and subjects, the longest ones being truncated as any other query */
+pub(crate) const CALIBRATION_QUERIES: [&str; 14] = [
+    "montceau les mines",
+    "le marquis de sade",
+    "alain chabat",
+    "la tour eiffel",
+    "victor hugo",
+    "emmanuel macron",
+    "la joconde",
+    "paris saint germain",
+    "les miserables",
+    "charles de gaulle",
+    "albert camus",
+    "jeanne d'arc",
+    "le petit prince",
+    "la marseillaise",
+];
+/** Seed of the truncation of the words matched for the calibration queries */
+pub(crate) const CALIBRATION_SEED: u64 = 0x616e_6167_7261_6d64;
+/** Best anagrams of several words kept for each query */
+pub(crate) const CALIBRATION_TOP: usize = 200;
+const NB_QUANTILES: usize = 100;
+
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ScoreCalibration {
+    /** Logarithm of the scores at `NB_QUANTILES + 1` evenly spaced quantiles, increasing. Empty when not calibrated */
+    log_quantiles: Vec<f32>,
+}
+
+/** Calibration of the scorers not calibrated yet, see `ScoreCalibration::normalize` */
+pub(crate) static UNCALIBRATED: ScoreCalibration = ScoreCalibration { log_quantiles: Vec::new() };
+
+impl ScoreCalibration {
+    /** From the raw scores of the anagrams of several words found for the calibration queries */
+    pub(crate) fn new(scores: &[f32]) -> ScoreCalibration {
+        let mut log_scores: Vec<f32> = scores.iter().filter(|&&s| s > 0.0 && s.is_finite()).map(|s| s.ln()).collect();
+        if log_scores.is_empty() {
+            return ScoreCalibration::default();
+        }
+        log_scores.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let last = log_scores.len() - 1;
+        let log_quantiles = (0..=NB_QUANTILES).map(|q| log_scores[q * last / NB_QUANTILES]).collect();
+        ScoreCalibration { log_quantiles }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.log_quantiles.is_empty()
+    }
+
+    /** Raw score of the anagrams of a single word: the best score of the calibration, 1 without calibration */
+    pub(crate) fn single_word_score(&self) -> f32 {
+        self.log_quantiles.last().map_or(1.0, |q| q.exp())
+    }
+
+    /** Score in [0, 1]: share of the calibration scores below `score`. Without calibration, `score / (1 + score)` */
+    pub(crate) fn normalize(&self, score: f32) -> f32 {
+        if self.is_empty() {
+            return (score / (1.0 + score)).clamp(0.0, 1.0);
+        }
+        if score <= 0.0 {
+            return 0.0;
+        }
+        /* The logarithm of `single_word_score` can be rounded below the last quantile */
+        if score >= self.single_word_score() {
+            return 1.0;
+        }
+        let log_score = score.ln();
+        let above = self.log_quantiles.partition_point(|&q| q <= log_score);
+        if above == 0 {
+            return 0.0;
+        }
+        if above == self.log_quantiles.len() {
+            return 1.0;
+        }
+        let (low, high) = (self.log_quantiles[above - 1], self.log_quantiles[above]);
+        let interpolation = (log_score - low) / (high - low);
+        (above - 1) as f32 / NB_QUANTILES as f32 + interpolation / NB_QUANTILES as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_are_normalized_by_quantile() {
+        let scores: Vec<f32> = (1..=1001).map(|i| i as f32).collect();
+        let calibration = ScoreCalibration::new(&scores);
+        assert_eq!(calibration.normalize(0.5), 0.0);
+        assert_eq!(calibration.normalize(2000.0), 1.0);
+        assert!((calibration.normalize(501.0) - 0.5).abs() < 0.01);
+        assert!(calibration.normalize(100.0) < calibration.normalize(101.0));
+        assert!((calibration.single_word_score() - 1001.0).abs() < 0.1);
+        assert_eq!(calibration.normalize(calibration.single_word_score()), 1.0);
+
+        let uncalibrated = ScoreCalibration::new(&[]);
+        assert!(uncalibrated.is_empty());
+        assert_eq!(uncalibrated.normalize(1.0), 0.5);
+    }
+}
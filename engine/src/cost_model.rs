@@ -0,0 +1,202 @@
+/**
+ * Latency model used to decide, for each query, how many matchable words the search can afford.
+ *
+ * The search builds every partial combination of matchable words, so its cost grows with the number of pairs of
+ * matchable words and exponentially with the depth of the expressions it has to build. The depth is estimated as the
+ * number of input letters divided by the mean length of the matchable words: inputs with a lot of repeated letters let
+ * many short words match, which makes the search tree much deeper. Then every complete candidate is scored, and the
+ * orderings of a deeper candidate cost more: the scoring costs the number of candidates times a cost per candidate
+ * growing with the depth. The candidates are the anagrams of the query whose words were all kept by the truncation,
+ * up to `MAX_COMPLETE_CANDIDATES` where the search stops.
+ *
+ * The coefficients are fitted on the times of the queries of `anagramdr bench`, of the calibration queries and of
+ * longer ones, at several truncations (release build). The estimation is the typical time of a query: half of them
+ * are slower, some more than twice as slow, so the queries are planned with a margin, see `LATENCY_MARGIN`.
+ */
+use crate::MAX_COMPLETE_CANDIDATES;
+
+/** Time we accept to spend on a single query */
+pub(crate) const LATENCY_BUDGET_MS: f32 = 500.0;
+/** The estimation of a planned query is at most the budget divided by this, which kept all the measures in it */
+const LATENCY_MARGIN: f32 = 2.5;
+/** Never truncate the matchable words under this size, reject the query instead */
+const MIN_MATCHABLE_WORDS: usize = 100;
+/** Never keep more matchable words than this, even for very easy queries */
+const MAX_MATCHABLE_WORDS: usize = 2000;
+/** Share of the kept words reserved to the words containing the most letters */
+const BIG_WORDS_RATIO: f32 = 0.05;
+/** Time spent filtering the index, whatever the number of matchable words */
+const BASE_COST_MS: f32 = 2.0;
+/** The costs below are given for this number of matchable words, this depth and this number of letters */
+const REFERENCE_WORDS: f32 = 100.0;
+const REFERENCE_DEPTH: f32 = 3.0;
+const REFERENCE_LETTERS: f32 = 16.0;
+/** Time of the search, growing about as the pairs of words and exponentially with the depth */
+const SEARCH_COST_MS: f32 = 1.28;
+const SEARCH_WORDS_GROWTH: f32 = 2.12;
+const SEARCH_DEPTH_GROWTH: f32 = 2.73;
+/** Anagrams of a query made of all its matchable words, more words and more letters making more of them */
+const NB_ANAGRAMS: f32 = 2760.0;
+const ANAGRAMS_WORDS_GROWTH: f32 = 1.41;
+const ANAGRAMS_LETTERS_GROWTH: f32 = 0.353;
+/**
+ * An anagram is found when the truncation kept all its words, so with the share of kept words to the power of its
+ * number of words, about 3.5 for the fitted queries
+ */
+const ANAGRAM_WORDS: f32 = 3.47;
+/** Time to score a complete candidate, its number of words, so of orderings, growing with the depth */
+const SCORING_COST_MS: f32 = 0.026;
+const SCORING_DEPTH_GROWTH: f32 = 1.3;
+/**
+ * Share of the cost of the whole search spent per word to include, the search growing from these words only. With
+ * random words to include in the queries of todo.md, a word took up to 8% of the cost and 5 words up to 72%.
+ */
+const COST_SHARE_PER_ROOT: f32 = 0.15;
+/**
+ * The search from a lot of words to include can cost more than the whole search: it finds the anagrams more slowly,
+ * so it stops later when enough are found. Up to 1.7 times in the same measures.
+ */
+const MAX_ROOTS_COST_SHARE: f32 = 2.0;
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum QueryPlan {
+    /** All matchable words can be searched */
+    Complete,
+    /** Only `nb_words` words can be searched, `nb_big_words` of them being the longest ones */
+    Truncated { nb_words: usize, nb_big_words: usize },
+    /** Even the smallest truncation would exceed the budget */
+    Rejected { estimated_ms: f32 },
+}
+
+#[derive(Debug)]
+pub(crate) struct QueryCost {
+    nb_letters: usize,
+    nb_matchable_words: usize,
+    depth: f32,
+    /** Share of the cost of the whole search, when the anagrams must contain one of the words to include */
+    roots_share: f32,
+}
+
+impl QueryCost {
+    /** `word_sizes` holds the number of letters of each matchable word */
+    pub(crate) fn new(nb_letters: usize, word_sizes: impl ExactSizeIterator<Item = usize>) -> QueryCost {
+        let nb_matchable_words = word_sizes.len();
+        let total_letters: usize = word_sizes.sum();
+        let mean_word_size = total_letters as f32 / nb_matchable_words.max(1) as f32;
+        QueryCost {
+            nb_letters,
+            nb_matchable_words,
+            depth: nb_letters as f32 / mean_word_size.max(1.0),
+            roots_share: 1.0,
+        }
+    }
+
+    /** The cost of the search growing from `nb_roots` words to include only, these words being matchable words */
+    pub(crate) fn with_roots(self, nb_roots: usize) -> QueryCost {
+        QueryCost {
+            roots_share: (nb_roots as f32 * COST_SHARE_PER_ROOT).min(MAX_ROOTS_COST_SHARE),
+            ..self
+        }
+    }
+
+    fn search_ms(&self, nb_words: usize) -> f32 {
+        let words_factor = (nb_words as f32 / REFERENCE_WORDS).powf(SEARCH_WORDS_GROWTH);
+        SEARCH_COST_MS * words_factor * (SEARCH_DEPTH_GROWTH * (self.depth - REFERENCE_DEPTH)).exp()
+    }
+
+    fn nb_complete_candidates(&self, nb_words: usize) -> f32 {
+        let nb_matchable_words = self.nb_matchable_words.max(1) as f32;
+        let words_factor = (nb_matchable_words / REFERENCE_WORDS).powf(ANAGRAMS_WORDS_GROWTH);
+        let letters_factor = (ANAGRAMS_LETTERS_GROWTH * (self.nb_letters as f32 - REFERENCE_LETTERS)).exp();
+        let kept_share = (nb_words as f32 / nb_matchable_words).min(1.0);
+        let nb_candidates = NB_ANAGRAMS * words_factor * letters_factor * kept_share.powf(ANAGRAM_WORDS);
+        nb_candidates.min(MAX_COMPLETE_CANDIDATES as f32)
+    }
+
+    fn scoring_ms(&self, nb_words: usize) -> f32 {
+        let cost_per_candidate = SCORING_COST_MS * (SCORING_DEPTH_GROWTH * (self.depth - REFERENCE_DEPTH)).exp();
+        self.nb_complete_candidates(nb_words) * cost_per_candidate
+    }
+
+    pub(crate) fn estimate_ms(&self, nb_words: usize) -> f32 {
+        BASE_COST_MS + self.roots_share * (self.search_ms(nb_words) + self.scoring_ms(nb_words))
+    }
+
+    /** The estimation grows with the number of words, the most affordable ones are found by bisection */
+    pub(crate) fn plan(&self, budget_ms: f32) -> QueryPlan {
+        let max_estimate_ms = budget_ms / LATENCY_MARGIN;
+        let (mut affordable, mut too_many) = (0, self.nb_matchable_words.min(MAX_MATCHABLE_WORDS) + 1);
+        while too_many - affordable > 1 {
+            let middle = (affordable + too_many) / 2;
+            if self.estimate_ms(middle) <= max_estimate_ms {
+                affordable = middle;
+            } else {
+                too_many = middle;
+            }
+        }
+        if self.nb_matchable_words <= affordable {
+            return QueryPlan::Complete;
+        }
+        if affordable < MIN_MATCHABLE_WORDS {
+            return QueryPlan::Rejected {
+                estimated_ms: self.estimate_ms(MIN_MATCHABLE_WORDS.min(self.nb_matchable_words)),
+            };
+        }
+        QueryPlan::Truncated {
+            nb_words: affordable,
+            nb_big_words: ((affordable as f32 * BIG_WORDS_RATIO) as usize).max(1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::iter::repeat_n;
+
+    #[test]
+    fn shallow_queries_keep_more_words() {
+        let word_sizes = repeat_n(6, 300);
+        assert_eq!(QueryCost::new(16, word_sizes).plan(LATENCY_BUDGET_MS), QueryPlan::Complete);
+    }
+
+    #[test]
+    fn deep_queries_are_truncated_then_rejected() {
+        let word_sizes = repeat_n(5, 700);
+        match QueryCost::new(18, word_sizes).plan(LATENCY_BUDGET_MS) {
+            QueryPlan::Truncated { nb_words, nb_big_words } => {
+                assert!((MIN_MATCHABLE_WORDS..700).contains(&nb_words));
+                assert!(nb_big_words > 0 && nb_big_words < nb_words);
+            }
+            plan => panic!("Unexpected plan {:?}", plan),
+        }
+        let word_sizes = repeat_n(2, 700);
+        assert!(matches!(
+            QueryCost::new(35, word_sizes).plan(LATENCY_BUDGET_MS),
+            QueryPlan::Rejected { .. }
+        ));
+    }
+
+    #[test]
+    fn queries_with_words_to_include_are_planned_by_their_number() {
+        let plan = |nb_roots| QueryCost::new(20, repeat_n(5, 300)).with_roots(nb_roots).plan(LATENCY_BUDGET_MS);
+        assert_eq!(plan(1), QueryPlan::Complete);
+        assert!(matches!(plan(5), QueryPlan::Truncated { .. }));
+        assert!(matches!(plan(40), QueryPlan::Rejected { .. }));
+    }
+
+    #[test]
+    fn scoring_costs_more_per_candidate_of_deeper_queries() {
+        let shallow = QueryCost::new(15, repeat_n(5, 2000));
+        let deep = QueryCost::new(20, repeat_n(5, 2000));
+        /* The search stops as many candidates are found */
+        assert_eq!(shallow.nb_complete_candidates(2000), MAX_COMPLETE_CANDIDATES as f32);
+        assert_eq!(deep.nb_complete_candidates(2000), MAX_COMPLETE_CANDIDATES as f32);
+        let ratio = deep.scoring_ms(2000) / shallow.scoring_ms(2000);
+        assert!((ratio - SCORING_DEPTH_GROWTH.exp()).abs() < 1e-3);
+        /* Below, the truncation keeps the anagrams whose words it all kept */
+        let query = QueryCost::new(16, repeat_n(5, 100));
+        let ratio = query.nb_complete_candidates(50) / query.nb_complete_candidates(100);
+        assert!((ratio - 0.5f32.powf(ANAGRAM_WORDS)).abs() < 1e-3);
+    }
+}
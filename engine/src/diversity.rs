@@ -0,0 +1,111 @@
+/**
+ * Diversification of the top of the results, which otherwise often share the same long word and only differ by
+ * their small words.
+ *
+ * The first `DIVERSIFIED_TOP` results are picked greedily by maximal marginal relevance (Carbonell and Goldstein,
+ * 1998): the next result is the one maximizing `(1 - diversity) * relevance - diversity * similarity`, the
+ * relevance being the score relative to the best one and the similarity the largest share of letters in common
+ * words with an already picked result. On top of that, a word longer than `SMALL_WORD_SIZE` cannot appear in more
+ * than `max_word_repeats` of them. The other results follow, in their original order.
+ */
+use crate::scorer::SMALL_WORD_SIZE;
+use crate::{Index, WordId};
+use rustc_hash::FxHashMap;
+
+/** Number of results reordered */
+const DIVERSIFIED_TOP: usize = 50;
+pub(crate) const DEFAULT_DIVERSITY: f32 = 0.3;
+pub(crate) const DEFAULT_MAX_WORD_REPEATS: usize = 5;
+
+/** Share of the letters of `a` in words also in `b`, both being anagrams of the same input */
+fn similarity(a: &[WordId], b: &[WordId], index: &Index) -> f32 {
+    let mut unmatched = b.to_vec();
+    let mut shared_letters = 0;
+    let mut nb_letters = 0;
+    for &word in a {
+        let word_letters = index.word_defs.nb_letters(word);
+        nb_letters += word_letters;
+        if let Some(position) = unmatched.iter().position(|&w| w == word) {
+            unmatched.swap_remove(position);
+            shared_letters += word_letters;
+        }
+    }
+    shared_letters as f32 / nb_letters.max(1) as f32
+}
+
+fn capped_words<'a>(words: &'a [WordId], index: &'a Index) -> impl Iterator<Item = WordId> + 'a {
+    words.iter().copied().filter(|&w| index.word_defs.nb_letters(w) > SMALL_WORD_SIZE)
+}
+
+/**
+ * New order of `anagrams`, given as their words and score, best first. `diversity` is in [0, 1], 0 keeping the
+ * order of the scores. A `max_word_repeats` of 0 does not cap the words.
+ */
+pub(crate) fn diversified_order(anagrams: &[(&[WordId], f32)], index: &Index, diversity: f32, max_word_repeats: usize) -> Vec<usize> {
+    if diversity <= 0.0 && max_word_repeats == 0 {
+        return (0..anagrams.len()).collect();
+    }
+    let diversity = diversity.clamp(0.0, 1.0);
+    /* Single words get the best score of the calibration, they are as relevant as the best anagram of several words */
+    let best_score = anagrams.iter().filter(|a| a.0.len() > 1).map(|a| a.1).fold(f32::MIN_POSITIVE, f32::max);
+    let relevance = |i: usize| (anagrams[i].1 / best_score).min(1.0);
+    let mut order = Vec::with_capacity(anagrams.len());
+    let mut is_picked = vec![false; anagrams.len()];
+    let mut max_similarity = vec![0.0f32; anagrams.len()];
+    let mut word_repeats: FxHashMap<WordId, usize> = FxHashMap::default();
+    while order.len() < DIVERSIFIED_TOP.min(anagrams.len()) {
+        let mut best: Option<(usize, f32)> = None;
+        for i in 0..anagrams.len() {
+            /* Anagrams are sorted by score: the following ones cannot do better */
+            let upper_bound = (1.0 - diversity) * relevance(i);
+            if best.is_some_and(|(_, value)| upper_bound < value) {
+                break;
+            }
+            let over_cap = max_word_repeats > 0
+                && capped_words(anagrams[i].0, index).any(|w| word_repeats.get(&w).is_some_and(|&n| n >= max_word_repeats));
+            if is_picked[i] || over_cap {
+                continue;
+            }
+            let value = upper_bound - diversity * max_similarity[i];
+            if best.is_none_or(|(_, best_value)| value > best_value) {
+                best = Some((i, value));
+            }
+        }
+        let Some((picked, _)) = best else { break };
+        is_picked[picked] = true;
+        order.push(picked);
+        for word in capped_words(anagrams[picked].0, index) {
+            *word_repeats.entry(word).or_default() += 1;
+        }
+        for i in (0..anagrams.len()).filter(|&i| !is_picked[i]) {
+            max_similarity[i] = max_similarity[i].max(similarity(anagrams[i].0, anagrams[picked].0, index));
+        }
+    }
+    order.extend((0..anagrams.len()).filter(|&i| !is_picked[i]));
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{test_index, word_id};
+    use crate::PosTag;
+
+    #[test]
+    fn repeated_long_words_are_spread() {
+        let mut index = test_index();
+        index.push_word("marquise", PosTag::NOUN, vec![], 0.0);
+        index.push_word("sombre", PosTag::ADJ, vec![], 0.0);
+        let [marquise, sombre, le, un, il] = ["marquise", "sombre", "le", "un", "il"].map(|w| word_id(&index, w));
+        let first = [marquise, le, le];
+        let second = [marquise, un, le];
+        let third = [marquise, il, un];
+        let other = [sombre, le, un];
+        let anagrams: Vec<(&[WordId], f32)> = vec![(&first, 10.0), (&second, 9.0), (&third, 8.0), (&other, 7.0)];
+
+        assert_eq!(diversified_order(&anagrams, &index, 0.0, 0), vec![0, 1, 2, 3]);
+        assert_eq!(diversified_order(&anagrams, &index, 0.0, 2), vec![0, 1, 3, 2]);
+        assert_eq!(diversified_order(&anagrams, &index, 0.5, 0), vec![0, 3, 2, 1]);
+        assert!(similarity(&second, &first, &index) > similarity(&other, &first, &index));
+    }
+}
@@ -0,0 +1,174 @@
+/**
+ * Optional word vectors, to measure how related the words of an anagram are to each other and to the input.
+ *
+ * The file (`EMBEDDINGS_PATH`) is in the text format of word2vec and fastText: one word per line followed by its
+ * components, after an optional "<nb words> <dimension>" header. Only the vectors of the words of the index are
+ * kept. They are normalized and quantized to bytes, so that cosine similarities are integer dot products and the
+ * index stays small.
+ */
+use crate::WordId;
+use rustc_hash::FxHashMap;
+use serde_derive::{Deserialize, Serialize};
+use std::io::BufRead;
+
+/** Components are stored as `round(component * QUANTIZATION_SCALE)`, after normalization */
+const QUANTIZATION_SCALE: f32 = 127.0;
+const NO_VECTOR: u32 = u32::MAX;
+/** The semantic factor is `exp(SEMANTIC_WEIGHT * semantic score)` */
+const SEMANTIC_WEIGHT: f32 = 1.0;
+
+/** How the meaning of an anagram should relate to its input */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SemanticPreference {
+    /** Words related to each other and to the input */
+    #[default]
+    Coherent,
+    /** Words related to each other, but unrelated to the input */
+    Ironic,
+}
+
+/** Semantic measures of an anagram, similarities being in [-1, 1] */
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub(crate) struct SemanticScore {
+    /** Mean similarity of the pairs of words of the anagram */
+    coherence: Option<f32>,
+    /** Mean similarity between the words of the anagram and the words of the input */
+    relatedness: Option<f32>,
+    /** Multiplies the score of the anagram */
+    pub(crate) factor: f32,
+}
+
+#[derive(Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub(crate) struct Embeddings {
+    dimension: usize,
+    /** `dimension` quantized components per vector */
+    components: Vec<i8>,
+    /** Vector of each word of the index, `NO_VECTOR` if it has none */
+    word_vectors: Vec<u32>,
+    /** Vector of each word string, to look up the words of the input */
+    vectors_by_word: FxHashMap<String, u32>,
+}
+
+impl Embeddings {
+    /** `word_ids` holds the id of each word string of the index */
+    pub(crate) fn load<R: BufRead>(reader: R, word_ids: &FxHashMap<String, WordId>, nb_words: usize) -> Embeddings {
+        let mut embeddings = Embeddings {
+            word_vectors: vec![NO_VECTOR; nb_words],
+            ..Embeddings::default()
+        };
+        for line in reader.lines() {
+            let Ok(line) = line else { continue };
+            let mut fields = line.split(' ').filter(|f| !f.is_empty());
+            let Some(word) = fields.next() else { continue };
+            let Some(&id) = word_ids.get(word) else { continue };
+            let Ok(vector) = fields.map(str::parse::<f32>).collect::<Result<Vec<f32>, _>>() else { continue };
+            if embeddings.dimension == 0 {
+                embeddings.dimension = vector.len();
+            }
+            let norm = vector.iter().map(|c| c * c).sum::<f32>().sqrt();
+            if vector.len() != embeddings.dimension || norm == 0.0 || embeddings.vectors_by_word.contains_key(word) {
+                continue;
+            }
+            let vector_id = (embeddings.components.len() / embeddings.dimension) as u32;
+            embeddings.components.extend(vector.iter().map(|c| (c / norm * QUANTIZATION_SCALE).round() as i8));
+            embeddings.word_vectors[id as usize] = vector_id;
+            embeddings.vectors_by_word.insert(String::from(word), vector_id);
+        }
+        embeddings
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    pub(crate) fn nb_vectors(&self) -> usize {
+        self.vectors_by_word.len()
+    }
+
+    pub(crate) fn word_vector(&self, word: WordId) -> Option<u32> {
+        self.word_vectors.get(word as usize).copied().filter(|&v| v != NO_VECTOR)
+    }
+
+    pub(crate) fn vector_of(&self, word: &str) -> Option<u32> {
+        self.vectors_by_word.get(word).copied()
+    }
+
+    fn vector(&self, vector: u32) -> &[i8] {
+        let start = vector as usize * self.dimension;
+        &self.components[start..start + self.dimension]
+    }
+
+    /** Cosine similarity */
+    fn similarity(&self, a: u32, b: u32) -> f32 {
+        let dot: i32 = self.vector(a).iter().zip(self.vector(b)).map(|(&x, &y)| x as i32 * y as i32).sum();
+        dot as f32 / (QUANTIZATION_SCALE * QUANTIZATION_SCALE)
+    }
+
+    fn mean_similarity(&self, pairs: impl Iterator<Item = (u32, u32)>) -> Option<f32> {
+        let (sum, count) = pairs.fold((0.0, 0), |(sum, count), (a, b)| (sum + self.similarity(a, b), count + 1));
+        (count > 0).then(|| sum / count as f32)
+    }
+
+    /**
+     * Semantic measures of `words`, the vectors of the input words being `input_vectors`. The words without vector
+     * are left out, None if no measure can be made.
+     */
+    pub(crate) fn semantic_score(
+        &self,
+        words: &[WordId],
+        input_vectors: &[u32],
+        preference: SemanticPreference,
+    ) -> Option<SemanticScore> {
+        let vectors: Vec<u32> = words.iter().filter_map(|&w| self.word_vector(w)).collect();
+        let coherence = self.mean_similarity(
+            vectors.iter().enumerate().flat_map(|(i, &a)| vectors[i + 1..].iter().map(move |&b| (a, b))),
+        );
+        let relatedness = self.mean_similarity(
+            vectors.iter().flat_map(|&a| input_vectors.iter().map(move |&b| (a, b))),
+        );
+        if coherence.is_none() && relatedness.is_none() {
+            return None;
+        }
+        let relatedness_sign = match preference {
+            SemanticPreference::Coherent => 1.0,
+            SemanticPreference::Ironic => -1.0,
+        };
+        let score = coherence.unwrap_or(0.0) + relatedness_sign * relatedness.unwrap_or(0.0);
+        Some(SemanticScore {
+            coherence,
+            relatedness,
+            factor: (SEMANTIC_WEIGHT * score).exp(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn semantic_scores() {
+        let file = "4 3\nchat 1 0.1 0\nchien 0.9 0.2 0\nmine 0 0 1\nabsent 1 1 1\n";
+        let word_ids: FxHashMap<String, WordId> =
+            [("chat", 0), ("chien", 1), ("mine", 2)].into_iter().map(|(w, id)| (String::from(w), id)).collect();
+        let embeddings = Embeddings::load(file.as_bytes(), &word_ids, 5);
+        assert_eq!(embeddings.nb_vectors(), 3);
+        assert_eq!(embeddings.word_vector(2), embeddings.vector_of("mine"));
+        assert_eq!(embeddings.word_vector(3), None);
+        let chat = embeddings.vector_of("chat").unwrap();
+        assert!((embeddings.similarity(chat, chat) - 1.0).abs() < 0.02);
+
+        let pets = embeddings.semantic_score(&[0, 1], &[], SemanticPreference::Coherent).unwrap();
+        let mixed = embeddings.semantic_score(&[0, 2], &[], SemanticPreference::Coherent).unwrap();
+        assert!(pets.coherence.unwrap() > 0.9 && mixed.coherence.unwrap().abs() < 0.02);
+        assert!(pets.factor > mixed.factor);
+        assert_eq!(pets.relatedness, None);
+
+        let input = [chat];
+        let coherent = embeddings.semantic_score(&[0, 1], &input, SemanticPreference::Coherent).unwrap();
+        let ironic = embeddings.semantic_score(&[0, 1], &input, SemanticPreference::Ironic).unwrap();
+        assert!(coherent.factor > pets.factor && pets.factor > ironic.factor);
+        assert_eq!(embeddings.semantic_score(&[4], &input, SemanticPreference::Coherent), None);
+    }
+}
@@ -0,0 +1,134 @@
+/**
+ * Cached binary index, so that the server does not have to parse the JSONL files and compute the pair stats at each
+ * start. This is not a zero-copy layout: the file is memory mapped and bincode deserializes the whole `Index` from the
+ * mapping into owned vectors and maps, without reading it into an intermediate buffer first. On the current lexicon
+ * the file is about 3MB and loads in a few dozen milliseconds, checksum included. The scores the scorers derive from
+ * the stats (`PairScores`) are not saved but computed again once loaded, which takes a few milliseconds.
+ *
+ * Layout of the file (integers are little endian):
+ * - `MAGIC`
+ * - format version (u32), compared to `INDEX_CACHE_VERSION`
+ * - checksum of the JSONL files the index was built from (u32), to detect a stale file
+ * - checksum of the payload (u32), to detect a corrupted file
+ * - the `Index`, serialized with bincode
+ */
+use crate::scorer::PairScores;
+use crate::Index;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"ANAGRDR\0";
+/** Bump this each time the layout of `Index` changes */
+const INDEX_CACHE_VERSION: u32 = 12;
+const HEADER_SIZE: usize = MAGIC.len() + 3 * 4;
+
+/**
+ * Checksum of the concatenated source files, None if one of them cannot be read. The files are read by chunks, as
+ * some of them (the embeddings) are too big to be read at once at each start
+ */
+pub(crate) fn source_checksum(paths: &[&str]) -> Option<u32> {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0; 1 << 16];
+    for path in paths {
+        let mut file = File::open(path).ok()?;
+        loop {
+            match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => hasher.update(&buffer[..read]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+    Some(hasher.finalize())
+}
+
+/**
+ * Save the index to `path`. The file is written next to it then renamed in place, so that a server
+ * loading `path` meanwhile (at start or on SIGHUP) maps either the previous file or the new one, never a partial one.
+ */
+pub(crate) fn save<P: AsRef<Path>>(index: &Index, path: P, source_checksum: u32) -> io::Result<()> {
+    let path = path.as_ref();
+    let payload = bincode::serialize(index).map_err(io::Error::other)?;
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut file = io::BufWriter::new(File::create(&tmp_path)?);
+    file.write_all(MAGIC)?;
+    file.write_all(&INDEX_CACHE_VERSION.to_le_bytes())?;
+    file.write_all(&source_checksum.to_le_bytes())?;
+    file.write_all(&crc32fast::hash(&payload).to_le_bytes())?;
+    file.write_all(&payload)?;
+    file.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+    std::fs::rename(&tmp_path, path)
+}
+
+/**
+ * Load the index from `path`. When `expected_source_checksum` is provided, the file is refused
+ * if it was built from other source files.
+ */
+pub(crate) fn load<P: AsRef<Path>>(path: P, expected_source_checksum: Option<u32>) -> Result<Index, String> {
+    let file = File::open(path).map_err(|e| format!("cannot open index file: {}", e))?;
+    // Safety: `save` never writes to an existing index file, it replaces it by a rename, so the mapped file is not
+    // modified while mapped, unless someone writes to it by other means
+    let mmap = unsafe { Mmap::map(&file) }.map_err(|e| format!("cannot map index file: {}", e))?;
+    if mmap.len() < HEADER_SIZE || &mmap[..MAGIC.len()] != MAGIC {
+        return Err(String::from("not an index file"));
+    }
+    let read_u32 = |offset: usize| u32::from_le_bytes(mmap[offset..offset + 4].try_into().unwrap());
+    let version = read_u32(MAGIC.len());
+    if version != INDEX_CACHE_VERSION {
+        return Err(format!("index file version is {}, expected {}", version, INDEX_CACHE_VERSION));
+    }
+    if let Some(expected) = expected_source_checksum {
+        if read_u32(MAGIC.len() + 4) != expected {
+            return Err(String::from("index file is stale, source files changed since it was built"));
+        }
+    }
+    let payload = &mmap[HEADER_SIZE..];
+    if crc32fast::hash(payload) != read_u32(MAGIC.len() + 8) {
+        return Err(String::from("index file is corrupted"));
+    }
+    let mut index: Index =
+        bincode::deserialize(payload).map_err(|e| format!("cannot deserialize index file: {}", e))?;
+    index.pair_scores = PairScores::new(&index);
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_index;
+
+    #[test]
+    fn index_cache_round_trip() {
+        let path = std::env::temp_dir().join(format!("anagramdr-index-{}.bin", std::process::id()));
+        let index = test_index();
+        save(&index, &path, 42).unwrap();
+        let loaded = load(&path, Some(42)).unwrap();
+        assert_eq!(loaded.original_letters, index.original_letters);
+        assert_eq!(loaded.word_defs.letters_ends, index.word_defs.letters_ends);
+        assert_eq!(loaded.readings, index.readings);
+        assert_eq!(loaded.reading_pair_stats, index.reading_pair_stats);
+        assert_eq!(loaded.pair_scores, index.pair_scores);
+        assert_eq!(loaded.pos_n_grams, index.pos_n_grams);
+        assert!(load(&path, None).is_ok());
+        assert!(load(&path, Some(43)).err().unwrap().contains("stale"));
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(load(&path, Some(42)).err().unwrap().contains("corrupted"));
+
+        // The file opened before saving again is left untouched
+        let mut previous = File::open(&path).unwrap();
+        save(&index, &path, 43).unwrap();
+        let mut previous_bytes = Vec::new();
+        previous.read_to_end(&mut previous_bytes).unwrap();
+        assert_eq!(previous_bytes, bytes);
+        assert!(load(&path, Some(43)).is_ok());
+        assert!(!path.with_extension("bin.tmp").exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
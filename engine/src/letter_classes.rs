@@ -0,0 +1,252 @@
+/**
+ * Which letters of the input can stand for which letters of the anagrams. The encoded letters are partitioned into
+ * classes of interchangeable letters, starting from the classes of the `SearchType`: all the letters having the same
+ * base letter for ROOT and PREFER_EXACT, each letter alone for EXACT. Each group of letters given with the query then
+ * becomes a class of its own: "ç" keeps "ç" apart from "c" in ROOT, "éèê" makes these interchangeable in EXACT. The
+ * letters of a group must have the same base letter, so that the bloom filters of the words still hold.
+ *
+ * Letters are compared through the key of their class, its smallest letter. Sorted by key, the letters of a class are
+ * next to each other, as `Index::check_contains_all_letters` needs.
+ */
+use crate::{char_to_u8, u8_to_char, Letters, SearchType, ALLOWED_CHARS};
+use serde_derive::Serialize;
+
+/** Default bonus of PREFER_EXACT, see `SearchOptions::accent_bonus` */
+pub(crate) const DEFAULT_ACCENT_BONUS: f32 = 1.5;
+pub(crate) static ROOT_CLASSES: LetterClasses = LetterClasses::preset(0b11111000);
+pub(crate) static EXACT_CLASSES: LetterClasses = LetterClasses::preset(0b11111111);
+
+/** A letter of an anagram having another diacritic than the input letter it stands for */
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct AccentSubstitution {
+    /** Position of the letter in the anagram, in characters */
+    position: usize,
+    letter: char,
+    /** Letter of the input it stands for */
+    input_letter: char,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct LetterClasses {
+    /** Key of the class of each encoded letter */
+    keys: [u8; 256],
+    /** Whether the keys increase with the letters, letters sorted by code being sorted by key as well */
+    sorted_by_code: bool,
+}
+
+impl LetterClasses {
+    const fn preset(mask: u8) -> LetterClasses {
+        let mut keys = [0; 256];
+        let mut letter = 0;
+        while letter < keys.len() {
+            keys[letter] = letter as u8 & mask;
+            letter += 1;
+        }
+        LetterClasses { keys, sorted_by_code: true }
+    }
+
+    /** `groups` are groups of interchangeable letters separated by commas, e.g. "éèê,àâ" */
+    pub(crate) fn new(search_type: SearchType, groups: &str) -> Result<LetterClasses, String> {
+        let mut classes = match search_type {
+            SearchType::EXACT => EXACT_CLASSES.clone(),
+            SearchType::ROOT | SearchType::PREFER_EXACT => ROOT_CLASSES.clone(),
+        };
+        for group in groups.split(',').map(str::trim).filter(|g| !g.is_empty()) {
+            let group = group.to_lowercase();
+            if let Some(c) = group.chars().find(|&c| !ALLOWED_CHARS.contains(c)) {
+                return Err(format!("Lettre inconnue dans les classes de lettres : {}", c));
+            }
+            let letters: Letters = group.chars().map(char_to_u8).collect();
+            if letters.iter().any(|l| l >> 3 != letters[0] >> 3) {
+                return Err(format!("Les lettres d'une classe doivent avoir la même lettre de base : {}", group));
+            }
+            classes.isolate(&letters);
+        }
+        /* Only 6 diacritics are encoded, the other codes are not letters */
+        let letters: Letters = (0..=u8::MAX).filter(|l| l & 0b111 <= 5).collect();
+        classes.sorted_by_code = letters.windows(2).all(|l| classes.keys[l[0] as usize] <= classes.keys[l[1] as usize]);
+        Ok(classes)
+    }
+
+    /** Make `letters` a class of their own, taking them out of their former classes */
+    fn isolate(&mut self, letters: &[u8]) {
+        for &letter in letters {
+            let former_key = self.keys[letter as usize];
+            if former_key != letter {
+                continue;
+            }
+            /* The rest of the former class is keyed by its smallest letter left */
+            let rest: Letters = (0..=u8::MAX)
+                .filter(|&l| self.keys[l as usize] == former_key && !letters.contains(&l))
+                .collect();
+            for &l in &rest {
+                self.keys[l as usize] = rest[0];
+            }
+        }
+        let key = *letters.iter().min().unwrap();
+        for &letter in letters {
+            self.keys[letter as usize] = key;
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn equal(&self, a: u8, b: u8) -> bool {
+        self.keys[a as usize] == self.keys[b as usize]
+    }
+
+    /** Keys of `letters`, sorted */
+    pub(crate) fn sorted_keys(&self, letters: &[u8]) -> Letters {
+        let mut keys: Letters = letters.iter().map(|&l| self.keys[l as usize]).collect();
+        if !self.sorted_by_code {
+            keys.sort_unstable();
+        }
+        keys
+    }
+
+    /** Sorted `letters` themselves when they are sorted by key already, else their keys sorted in `buffer` */
+    pub(crate) fn sorted_by_key<'a>(&self, letters: &'a [u8], buffer: &'a mut Letters) -> &'a [u8] {
+        if self.sorted_by_code {
+            return letters;
+        }
+        buffer.clear();
+        buffer.extend(letters.iter().map(|&l| self.keys[l as usize]));
+        buffer.sort_unstable();
+        buffer
+    }
+}
+
+/** Number of letters of the anagram with another diacritic than the input letters they stand for, both sorted */
+pub(crate) fn accent_substitutions(input_letters: &[u8], anagram_letters: &[u8]) -> usize {
+    let (mut i, mut j, mut kept) = (0, 0, 0);
+    while i < input_letters.len() && j < anagram_letters.len() {
+        match input_letters[i].cmp(&anagram_letters[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                kept += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    anagram_letters.len() - kept
+}
+
+/**
+ * Letters of `anagram` with another diacritic than the input letters they stand for, the input letters being the same
+ * as possible
+ */
+pub(crate) fn substituted_letters(input_letters: &[u8], anagram: &str, classes: &LetterClasses) -> Vec<AccentSubstitution> {
+    let mut unused: Letters = input_letters.to_vec();
+    let letters: Vec<(usize, u8)> =
+        anagram.chars().enumerate().filter(|(_, c)| ALLOWED_CHARS.contains(*c)).map(|(i, c)| (i, char_to_u8(c))).collect();
+    let mut substituted = vec![];
+    for &(position, letter) in &letters {
+        match unused.iter().position(|&l| l == letter) {
+            Some(i) => {
+                unused.swap_remove(i);
+            }
+            None => substituted.push((position, letter)),
+        }
+    }
+    /* What is left of the input is what the substituted letters stand for, class by class */
+    substituted
+        .into_iter()
+        .filter_map(|(position, letter)| {
+            let i = unused.iter().position(|&l| classes.equal(l, letter))?;
+            Some(AccentSubstitution { position, letter: u8_to_char(letter), input_letter: u8_to_char(unused.swap_remove(i)) })
+        })
+        .collect()
+}
+
+/**
+ * Position in `anagram` of each character of `input`, in characters, None for the characters left out. A letter goes to
+ * the same letter if it can, else to a letter of its class. Repeated letters keep their order, so that they do not
+ * cross when animated. Spaces go to the spaces between the words, in order, and the other characters are left out.
+ */
+pub(crate) fn letter_mapping(input: &str, anagram: &str, classes: &LetterClasses) -> Vec<Option<usize>> {
+    let input: Vec<char> = input.chars().map(|c| c.to_lowercase().next().unwrap_or(c)).collect();
+    let output: Vec<char> = anagram.chars().collect();
+    let mut mapping = vec![None; input.len()];
+    let mut taken = vec![false; output.len()];
+    for same_letter in [true, false] {
+        for (i, &c) in input.iter().enumerate() {
+            if mapping[i].is_some() || !ALLOWED_CHARS.contains(c) {
+                continue;
+            }
+            let found = (0..output.len()).find(|&j| {
+                !taken[j]
+                    && ALLOWED_CHARS.contains(output[j])
+                    && if same_letter { output[j] == c } else { classes.equal(char_to_u8(output[j]), char_to_u8(c)) }
+            });
+            if let Some(j) = found {
+                taken[j] = true;
+                mapping[i] = Some(j);
+            }
+        }
+    }
+    let mut spaces = (0..output.len()).filter(|&j| output[j] == ' ');
+    for (i, c) in input.iter().enumerate() {
+        if c.is_whitespace() {
+            mapping[i] = spaces.next();
+        }
+    }
+    mapping
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{str_to_u8, Index};
+
+    fn sorted(word: &str) -> Letters {
+        let mut letters = str_to_u8(word);
+        letters.sort();
+        letters
+    }
+
+    #[test]
+    fn groups_refine_the_search_type() {
+        let (c, cedilla) = (char_to_u8('c'), char_to_u8('ç'));
+        let (e, acute, grave, diaeresis) = (char_to_u8('e'), char_to_u8('é'), char_to_u8('è'), char_to_u8('ë'));
+        let classes = LetterClasses::new(SearchType::ROOT, "ç").unwrap();
+        assert!(!classes.equal(c, cedilla) && classes.equal(e, acute));
+        assert!(classes.sorted_by_code);
+
+        let classes = LetterClasses::new(SearchType::EXACT, "éèê, ").unwrap();
+        assert!(classes.equal(acute, grave) && !classes.equal(e, acute) && !classes.equal(c, cedilla));
+
+        /* e and é apart from è: the keys do not follow the codes anymore */
+        let classes = LetterClasses::new(SearchType::ROOT, "eé").unwrap();
+        assert!(classes.equal(e, acute) && !classes.equal(e, grave) && classes.equal(grave, diaeresis));
+        assert!(!classes.sorted_by_code);
+        let pool = classes.sorted_keys(&sorted("eè"));
+        assert!(Index::check_contains_all_letters(&pool, &classes.sorted_keys(&sorted("éè")), &classes));
+        assert!(!Index::check_contains_all_letters(&pool, &classes.sorted_keys(&sorted("éé")), &classes));
+
+        assert!(LetterClasses::new(SearchType::ROOT, "eé,aé").is_err());
+        assert!(LetterClasses::new(SearchType::ROOT, "e1").is_err());
+    }
+
+    #[test]
+    fn substituted_accents_are_counted() {
+        assert_eq!(accent_substitutions(&sorted("étete"), &sorted("étété")), 2);
+        assert_eq!(accent_substitutions(&sorted("étété"), &sorted("téété")), 0);
+        assert_eq!(accent_substitutions(&sorted("ça"), &sorted("ca")), 1);
+
+        let substituted = substituted_letters(&sorted("etete"), "été te", &ROOT_CLASSES);
+        let positions: Vec<usize> = substituted.iter().map(|s| s.position).collect();
+        assert_eq!(positions, vec![0, 2]);
+        assert_eq!(substituted[0], AccentSubstitution { position: 0, letter: 'é', input_letter: 'e' });
+        assert_eq!(substituted_letters(&sorted("étete"), "tê été", &ROOT_CLASSES).len(), 2);
+    }
+
+    #[test]
+    fn input_letters_are_mapped_in_order() {
+        assert_eq!(letter_mapping("Le lit.", "il tel", &ROOT_CLASSES), vec![Some(1), Some(4), Some(2), Some(5), Some(0), Some(3), None]);
+        /* The same letter first, whatever the order */
+        assert_eq!(letter_mapping("eé", "ée", &ROOT_CLASSES), vec![Some(1), Some(0)]);
+        assert_eq!(letter_mapping("ee", "éè", &ROOT_CLASSES), vec![Some(0), Some(1)]);
+        assert_eq!(letter_mapping("a  b", "ab", &ROOT_CLASSES), vec![Some(0), None, None, Some(1)]);
+    }
+}
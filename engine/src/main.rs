@@ -1,45 +1,87 @@
+mod agreement;
+mod calibration;
+mod cost_model;
+mod diversity;
+mod embeddings;
+mod index_cache;
+mod letter_classes;
+mod overrides;
+mod pos_model;
+mod reuse;
+mod scorer;
+mod sorting;
+
+use calibration::{ScoreCalibration, CALIBRATION_QUERIES, CALIBRATION_SEED, CALIBRATION_TOP, UNCALIBRATED};
+use cost_model::{QueryCost, QueryPlan, LATENCY_BUDGET_MS};
+use diversity::{diversified_order, DEFAULT_DIVERSITY, DEFAULT_MAX_WORD_REPEATS};
+use embeddings::{Embeddings, SemanticPreference, SemanticScore};
+use letter_classes::{accent_substitutions, letter_mapping, substituted_letters, AccentSubstitution, LetterClasses, DEFAULT_ACCENT_BONUS, ROOT_CLASSES};
+use pos_model::PosNGramModel;
+use reuse::{input_reuse, InputReuse, DEFAULT_REUSE_PENALTY};
+use sorting::{main_word, sorted_order, GroupBy, SortBy};
+use scorer::{
+    best_readings, morph_pair_score, scorer_by_name, PairScores, Scorer, ScoreBreakdown, DEFAULT_SCORER,
+    EXHAUSTIVE_ORDERING_SIZE, SCORERS,
+};
 use itertools::Itertools;
 use serde_json::Value;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io::{self, BufRead};
 use std::ops::Range;
 use std::path::Path;
 use std::str::{self, FromStr};
+use std::sync::{Arc, RwLock};
 use std::time::Instant;
-use strum_macros::EnumString;
+use strum_macros::{Display, EnumString};
+use tokio::signal::unix::{signal, SignalKind};
 use unicode_normalization::char::{compose, decompose_canonical};
 use urlencoding::decode;
 use warp::Filter;
 use warp::http::StatusCode;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet, FxHasher};
 use rayon::prelude::*;
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::SeedableRng;
 
 const ALLOWED_CHARS: &str = "aàâäbcçdeéèêëfghiîïjklmnoôÔöÖpqrstuûüùvwxyz";
-const MAX_EXPR_SIZE: usize = 6;
-const MAX_MATCHABLE_WORDS: usize = 600;
-const NB_BIG_WORDS_INCLUDED : usize = 30;
-const MAX_QUERY_LETTERS: usize = 25;
+const MAX_EXPR_SIZE: usize = 10;
+/** The search stops once it found this number of anagrams */
+const MAX_COMPLETE_CANDIDATES: usize = 10000;
+const WORDS_PATH: &str = "data/words.jsonl";
+const TAGGING_STATS_PATH: &str = "data/tagging_stats.jsonl";
+const POS_N_GRAMS_PATH: &str = "data/pos_n_grams.jsonl";
+/** Optional: the scoring backs off to the POS stats only when this file is missing */
+const WORD_BIGRAMS_PATH: &str = "data/word_bigrams.jsonl";
+/** Optional: anagrams are not scored by meaning when this file is missing */
+const EMBEDDINGS_PATH: &str = "data/embeddings.vec";
+/** Optional: fixes of the words applied at runtime, see `overrides` */
+const OVERRIDES_PATH: &str = "data/overrides.jsonl";
+/** Cached binary index, see `anagramdr build-index` and `index_cache` */
+const INDEX_CACHE_PATH: &str = "data/index.bin";
+/** How much common words are favoured over rare ones by default, see `Index::commonness_factor` */
+const DEFAULT_COMMONNESS_WEIGHT: f32 = 0.5;
 
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
-#[derive(PartialOrd, Ord, PartialEq, Eq, Debug, EnumString, Hash, Copy, Clone)]
+#[derive(PartialOrd, Ord, PartialEq, Eq, Debug, EnumString, Display, Hash, Copy, Clone, Serialize, Deserialize)]
 enum Gender {
     Fem,
     Masc,
 }
 
-#[derive(PartialOrd, Ord, PartialEq, Eq, Debug, EnumString, Hash, Copy, Clone)]
+#[derive(PartialOrd, Ord, PartialEq, Eq, Debug, EnumString, Display, Hash, Copy, Clone, Serialize, Deserialize)]
 enum Number {
     Sing,
     Plur,
 }
 
-#[derive(PartialOrd, Ord, PartialEq, Eq, Debug, EnumString, Hash, Copy, Clone)]
+#[derive(PartialOrd, Ord, PartialEq, Eq, Debug, EnumString, Display, Hash, Copy, Clone, Serialize, Deserialize)]
 enum Person {
     #[strum(serialize = "1")]
     One,
@@ -49,8 +91,39 @@ enum Person {
     Three,
 }
 
+#[derive(PartialOrd, Ord, PartialEq, Eq, Debug, EnumString, Display, Hash, Copy, Clone, Serialize, Deserialize)]
+enum VerbForm {
+    Fin,
+    Inf,
+    Part,
+    Ger,
+}
+
+#[derive(PartialOrd, Ord, PartialEq, Eq, Debug, EnumString, Display, Hash, Copy, Clone, Serialize, Deserialize)]
+enum Tense {
+    Past,
+    Pres,
+    Imp,
+    Fut,
+}
+
+#[derive(PartialOrd, Ord, PartialEq, Eq, Debug, EnumString, Display, Hash, Copy, Clone, Serialize, Deserialize)]
+enum Mood {
+    Ind,
+    Cnd,
+    Imp,
+    Sub,
+}
+
+#[derive(PartialOrd, Ord, PartialEq, Eq, Debug, EnumString, Display, Hash, Copy, Clone, Serialize, Deserialize)]
+enum Definite {
+    Def,
+    Ind,
+}
+
 // https://universaldependencies.org/u/pos/
-#[derive(PartialOrd, Ord, PartialEq, Eq, Debug, EnumString, Hash, Copy, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(PartialOrd, Ord, PartialEq, Eq, Debug, EnumString, Hash, Copy, Clone, Serialize, Deserialize)]
 enum PosTag {
     ADJ,
     ADP,
@@ -73,52 +146,133 @@ enum PosTag {
     SPACE,
 }
 
-#[derive(PartialOrd, Ord, PartialEq, Eq, Debug, Hash, Copy, Clone)]
+#[derive(PartialOrd, Ord, PartialEq, Eq, Debug, Hash, Copy, Clone, Default, Serialize, Deserialize)]
 struct Morph {
     gender: Option<Gender>,
     number: Option<Number>,
     person: Option<Person>,
+    verb_form: Option<VerbForm>,
+    tense: Option<Tense>,
+    mood: Option<Mood>,
+    definite: Option<Definite>,
 }
 
 impl Morph {
+    /** Features we do not know (other keys, or values like "Plur,Sing") are left out */
     fn from_serde_map(serde_map: &serde_json::Map<String, serde_json::Value>) -> Morph {
-        let mut morph = Morph {
-            number: None,
-            gender: None,
-            person: None,
-        };
-        serde_map.iter().for_each(|(k, v)| match k.as_str() {
-            "Number" => morph.number = Some(Number::from_str(v.as_str().unwrap()).unwrap()),
-            "Gender" => morph.gender = Some(Gender::from_str(v.as_str().unwrap()).unwrap()),
-            "Person" => morph.person = Some(Person::from_str(v.as_str().unwrap()).unwrap()),
-            _ => unreachable!(),
+        let mut morph = Morph::default();
+        serde_map.iter().for_each(|(k, v)| {
+            let v = v.as_str().unwrap_or_default();
+            match k.as_str() {
+                "Number" => morph.number = Number::from_str(v).ok(),
+                "Gender" => morph.gender = Gender::from_str(v).ok(),
+                "Person" => morph.person = Person::from_str(v).ok(),
+                "VerbForm" => morph.verb_form = VerbForm::from_str(v).ok(),
+                "Tense" => morph.tense = Tense::from_str(v).ok(),
+                "Mood" => morph.mood = Mood::from_str(v).ok(),
+                "Definite" => morph.definite = Definite::from_str(v).ok(),
+                _ => {}
+            }
         });
         morph
     }
+
+    /** Known features, named and valued as in the words file */
+    fn features(&self) -> BTreeMap<&'static str, String> {
+        let features = [
+            ("Gender", self.gender.map(|v| v.to_string())),
+            ("Number", self.number.map(|v| v.to_string())),
+            ("Person", self.person.map(|v| v.to_string())),
+            ("VerbForm", self.verb_form.map(|v| v.to_string())),
+            ("Tense", self.tense.map(|v| v.to_string())),
+            ("Mood", self.mood.map(|v| v.to_string())),
+            ("Definite", self.definite.map(|v| v.to_string())),
+        ];
+        features.into_iter().filter_map(|(name, value)| Some((name, value?))).collect()
+    }
+
+    /** The tagging stats only record gender, number and person */
+    fn tagging_stats_key(&self) -> Morph {
+        Morph {
+            gender: self.gender,
+            number: self.number,
+            person: self.person,
+            ..Morph::default()
+        }
+    }
 }
 
-#[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Copy, Clone)]
+#[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Copy, Clone, Serialize, Deserialize)]
 struct PosMorph {
     pos: PosTag,
     morph: Morph,
 }
 
 type Letters = Vec<u8>;
-#[derive(Debug, Clone)]
-struct Word {
-    letters_sorted_range: Range<u32>,
-    letters_original_range: Range<u32>,
-    pos_tag: PosTag,
-    morph_tags: Vec<Morph>,
-    bloom_letters: u32,
+/** Position of a word in `Index::word_defs` */
+type WordId = u32;
+/** Position of a set of morphologies in `Index::morph_sets` */
+type MorphSetId = u16;
+/** Position of a reading in `Index::readings` */
+type ReadingId = u16;
+/** Position of a set of readings in `Index::reading_sets` */
+type ReadingSetId = u16;
+/** POS tag, morphologies and probability, if known, of each reading of a word */
+type WordReadings = Vec<(PosTag, Vec<Morph>, Option<f32>)>;
+/** Orderings of the words of an anagram with their score, best first */
+type Orderings = Vec<(Vec<WordId>, f32)>;
+
+/**
+ * A POS tag and the morphologies a word was seen with. Only a hundred or so distinct readings exist in the
+ * whole lexicon, so they are interned and shared by all the words having the same one.
+ */
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone, Serialize, Deserialize)]
+struct Reading {
+    pos: PosTag,
+    morph_set: MorphSetId,
+}
+
+/**
+ * Definitions of the words of the vocab, stored as a struct of arrays indexed by WordId, to keep the
+ * columns read while searching (letters, bloom filters) packed together.
+ *
+ * The words of the words file are sorted by increasing number of letters. The words added by the overrides come
+ * after them, whatever their length, so that the ids of the other words do not change: what needs the words by
+ * length sorts them again, see `Index::filter_matchable_words`.
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct WordDefs {
+    /** Letters of word `i` are at `letters_ends[i - 1]..letters_ends[i]` in both `original_letters` and `sorted_letters` */
+    letters_ends: Vec<u32>,
+    bloom_letters: Vec<u32>,
+    reading_sets: Vec<ReadingSetId>,
+    /** Occurences per million words in the reference corpora, 0 when unknown */
+    frequencies: Vec<f32>,
+}
+
+impl WordDefs {
+    fn len(&self) -> usize {
+        self.letters_ends.len()
+    }
+
+    #[inline(always)]
+    fn letters_range(&self, word: WordId) -> Range<usize> {
+        let start = if word == 0 { 0 } else { self.letters_ends[word as usize - 1] };
+        start as usize..self.letters_ends[word as usize] as usize
+    }
+
+    #[inline(always)]
+    fn nb_letters(&self, word: WordId) -> usize {
+        self.letters_range(word).len()
+    }
 }
 
 // remove all elements from original that are in matched_words
-fn remove_elems(original: &mut Vec<u8>, matched_word: &[u8], search_type: SearchType) {
+fn remove_elems(original: &mut Vec<u8>, matched_word: &[u8], classes: &LetterClasses) {
     let lengths = (original.len(), matched_word.len()); // pool, searched
     let mut indexes = (0, 0); // pool, searched
     while indexes.0 < lengths.0 && indexes.1 < lengths.1 {
-        if encoded_chars_equal(matched_word[indexes.1], original[indexes.0], search_type) {
+        if classes.equal(matched_word[indexes.1], original[indexes.0]) {
             original.remove(indexes.0);
             indexes.1 += 1;
         } else if matched_word[indexes.1] > original[indexes.0] {
@@ -137,16 +291,9 @@ where
     Ok(io::BufReader::new(file).lines())
 }
 
-type PosTagNGram = (
-    Option<PosTag>,
-    Option<PosTag>,
-    Option<PosTag>,
-    Option<PosTag>,
-);
-
 // static PRIORITY_WORDS:  &'static [&'static str] = &["ce", "cet", "cette", "un", "une", "le", "la", "de", "du", "sur"];
 
-#[derive(Clone)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 struct Index {
     /**
      * Contains the word as found in the entry corpus, as positions in "chars". If vocab is ["bonjour", "toi"] it will contain
@@ -159,23 +306,262 @@ struct Index {
      */
     sorted_letters: Letters,
     /** Contain all the words of the entry vocab */
-    word_defs: Vec<Word>,
+    word_defs: WordDefs,
+    /** Interned sets of morphologies, referenced by `readings` */
+    morph_sets: Vec<Vec<Morph>>,
+    /** Interned readings, referenced by `reading_sets` */
+    readings: Vec<Reading>,
+    /**
+     * Interned readings of each word, referenced by `word_defs`, the most probable first: it is the main reading.
+     * Homographs are a single word with several readings.
+     */
+    reading_sets: Vec<Vec<ReadingId>>,
+    /** Position of each reading set in `reading_sets`, rebuilt when the first word is interned after loading */
+    #[serde(skip)]
+    reading_set_ids: FxHashMap<Vec<ReadingId>, ReadingSetId>,
+    /**
+     * Probability of each reading of the words whose readings are not equally probable, relative to the most
+     * probable one, in the order of their reading set
+     */
+    reading_probabilities: FxHashMap<WordId, Vec<f32>>,
+    /**
+     * Best tagging stat of each pair of readings over all their morphologies, `readings.len()` x `readings.len()`, 0 if
+     * none of them were seen together. Weighted by the scorers, see `scorer::PairScores`
+     */
+    reading_pair_stats: Vec<f32>,
+    /** Scores of the pairs of readings, computed from the stats once the index is built or loaded */
+    #[serde(skip)]
+    pair_scores: PairScores,
+    /**
+     * Mean number of letters of the words. It used to be the mean length in bytes of the lines of the words file,
+     * accented letters counting twice: it is now computed from the index in `finalize`, whatever it was loaded from
+     */
     mean_word_size: f32,
     tagging_stats: FxHashMap<(PosMorph, PosMorph), f32>,
-    pos_n_grams: FxHashMap<PosTagNGram, f32>,
+    pos_n_grams: PosNGramModel,
+    /** Occurences of pairs of consecutive words in the corpus (square root, as the tagging stats) */
+    word_bigrams: FxHashMap<(WordId, WordId), f32>,
+    embeddings: Embeddings,
+    /** Inflected forms of each lemma, a word without lemma in the words file being the only form of its own */
+    forms_by_lemma: FxHashMap<String, Vec<WordId>>,
+    /** Normalisation of the scores of each scorer, by name, see `calibration` */
+    score_calibrations: FxHashMap<String, ScoreCalibration>,
+    /** Words removed by the overrides, never matched. Overrides are applied after loading, so this is not saved */
+    #[serde(skip)]
+    removed_words: FxHashSet<WordId>,
 }
 
-#[derive(PartialEq, EnumString, Copy, Clone, Default, Serialize, Deserialize, Debug)]
+/** Which letters can stand for each other by default, see `LetterClasses` */
+#[derive(PartialEq, Eq, Hash, EnumString, Copy, Clone, Default, Serialize, Deserialize, Debug)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 enum SearchType {
+    /** Letters with the same base letter */
     #[default]
     ROOT,
+    /** Only the same letters */
     EXACT,
+    /** As ROOT, the anagrams keeping the accents of the input being favoured, see `SearchOptions::accent_bonus` */
+    PREFER_EXACT,
+}
+
+/** How a word to include or exclude, given in a query, is matched with the words of the index */
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Default, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum MatchMode {
+    /** The words having the same letters, compared according to the `SearchType` */
+    #[default]
+    Word,
+    /** Any inflected form of the lemma */
+    Lemma,
+}
+
+/** Most orderings that can be asked for a single set of words */
+const MAX_ORDERINGS_PER_RESULT: usize = 5;
+
+#[derive(Debug, Clone)]
+struct SearchOptions {
+    search_type: SearchType,
+    /** Groups of interchangeable letters on top of the search type, separated by commas, see `LetterClasses::new` */
+    letter_classes: String,
+    /**
+     * Divides the score for each letter of the anagram having another diacritic than the input letter it stands for,
+     * 1 for no bonus to the anagrams keeping the accents of the input
+     */
+    accent_bonus: f32,
+    /** Drop the anagrams with more letters not keeping the accent of the input, no limit if None */
+    max_accent_substitutions: Option<usize>,
+    /** Return the letters of each anagram not keeping the accent of the input, see `AnagramDetails::substitutions` */
+    report_substitutions: bool,
+    /** Return where each character of the input goes in each anagram, see `AnagramDetails::letter_mapping` */
+    map_letters: bool,
+    /** Return the words of each anagram with their reading, see `AnagramDetails::tokens` */
+    tokens: bool,
+    /** Every anagram contains one of the words matching it, see `Index::words_matching` */
+    word_to_include: String,
+    include_mode: MatchMode,
+    /** No anagram contains any of the words matching it */
+    word_to_exclude: String,
+    exclude_mode: MatchMode,
+    /** How many orderings of each set of words to return, see `AnagramDetails::orderings` */
+    orderings_per_result: usize,
+    /** Return the breakdown of the score of each anagram, see `AnagramDetails::explanation` */
+    explain: bool,
+    /** Only keep the orderings without agreement violations, see `agreement::violations` */
+    grammatical_only: bool,
+    /** Name of the `Scorer` ranking the anagrams */
+    scorer: String,
+    /** How the meaning of the anagrams should relate to the input, when word vectors are loaded */
+    semantic: SemanticPreference,
+    /** Multiplies the score for each word or long substring taken from the input, see `reuse::input_reuse` */
+    reuse_penalty: f32,
+    /** Drop the anagrams taking any word or long substring from the input */
+    forbid_reuse: bool,
+    /** How much common words are favoured over rare ones, see `Index::commonness_factor` */
+    commonness_weight: f32,
+    /** How much the top results are diversified, from 0 (ranked by score only) to 1, see `diversity` */
+    diversity: f32,
+    /** How many top results a word that is not small may appear in, 0 for no limit */
+    max_word_repeats: usize,
+    /** Order of the results, after the diversification, see `sorting` */
+    sort_by: SortBy,
+    group_by: GroupBy,
+    /**
+     * Seed of the random truncation of the words to match, see `get_matchable_words`. If None, it is derived from the
+     * query, see `truncation_seed`, so that the pages of a truncated query are taken from the same results.
+     */
+    truncation_seed: Option<u64>,
+    /** Results skipped, once sorted and grouped */
+    offset: usize,
+    /** Most results returned after the skipped ones, no limit if None */
+    limit: Option<usize>,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            search_type: SearchType::default(),
+            letter_classes: String::new(),
+            accent_bonus: 1.0,
+            max_accent_substitutions: None,
+            report_substitutions: false,
+            map_letters: false,
+            tokens: false,
+            word_to_include: String::new(),
+            include_mode: MatchMode::default(),
+            word_to_exclude: String::new(),
+            exclude_mode: MatchMode::default(),
+            orderings_per_result: 1,
+            explain: false,
+            grammatical_only: false,
+            scorer: String::from(DEFAULT_SCORER),
+            semantic: SemanticPreference::default(),
+            reuse_penalty: DEFAULT_REUSE_PENALTY,
+            forbid_reuse: false,
+            commonness_weight: DEFAULT_COMMONNESS_WEIGHT,
+            diversity: DEFAULT_DIVERSITY,
+            max_word_repeats: DEFAULT_MAX_WORD_REPEATS,
+            sort_by: SortBy::default(),
+            group_by: GroupBy::default(),
+            truncation_seed: None,
+            offset: 0,
+            limit: None,
+        }
+    }
 }
 
 #[derive(Serialize)]
 struct AnagramResult {
-    anagrams: Vec<(String, f32)>,
-    was_truncated: bool
+    /** Best ordering of each anagram, its score, and its score normalised to [0, 1], see `calibration` */
+    anagrams: Vec<(String, f32, f32)>,
+    /** Number of anagrams found, before `SearchOptions::offset` and `SearchOptions::limit` */
+    total: usize,
+    /** Main word of each anagram, in the same order as `anagrams`, when grouped by it */
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    groups: Vec<String>,
+    was_truncated: bool,
+    /** Additional information on each anagram, in the same order as `anagrams`. Empty when none was asked for */
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    details: Vec<AnagramDetails>,
+}
+
+#[derive(Serialize, Default)]
+struct AnagramDetails {
+    /** All the orderings kept for this anagram, best first, when more than one was asked for */
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    orderings: Vec<(String, f32)>,
+    /** How the score of the best ordering was computed, when asked for */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explanation: Option<ScoreBreakdown>,
+    /** Semantic measures of the anagram, when asked for with the explanation and word vectors are loaded */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    semantics: Option<SemanticScore>,
+    /** What the anagram takes from the input, when asked for with the explanation and it takes anything */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reuse: Option<InputReuse>,
+    /** Factor for how common the words of the anagram are, when asked for with the explanation */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commonness: Option<f32>,
+    /** Factor for the accents of the input not kept, when asked for with the explanation and there is a bonus */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    accents: Option<f32>,
+    /** Letters of the best ordering not keeping the accent of the input, when asked for */
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    substitutions: Vec<AccentSubstitution>,
+    /**
+     * Position in the best ordering of each character of the input, punctuation and spaces included, null for the
+     * characters left out, when asked for. Positions are in characters
+     */
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    letter_mapping: Vec<Option<usize>>,
+    /** Words of the best ordering with the reading chosen in context, when asked for */
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tokens: Vec<Token>,
+}
+
+/** A word of an anagram, see `AnagramDetails::tokens` */
+#[derive(Serialize, Debug, PartialEq)]
+struct Token {
+    surface: String,
+    pos: PosTag,
+    /**
+     * Morphology of the reading fitting the neighbouring words best, in Universal Dependencies features, empty if the
+     * reading has none
+     */
+    morph: BTreeMap<&'static str, String>,
+    /** Other morphologies of the reading, fitting the neighbouring words less or as well */
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    other_morphs: Vec<BTreeMap<&'static str, String>>,
+    /**
+     * Id of the word in the index, stable as long as the index is not rebuilt. The ids of the words added by the
+     * overrides change when they are reloaded, see `overrides`.
+     */
+    word_id: WordId,
+}
+
+/** An anagram being ranked, see `Index::find_anagrams_reverse` */
+struct ScoredAnagram {
+    /** Best ordering, as returned */
+    text: String,
+    orderings: Orderings,
+    semantics: Option<SemanticScore>,
+    reuse: InputReuse,
+    commonness: f32,
+    accents: f32,
+    /** Product of the factors above, multiplying the scores of the orderings */
+    query_factor: f32,
+}
+
+/** Seed of the truncation of the words to match, from what decides which words fit the query, the pages aside */
+fn truncation_seed(sorted_input: &[u8], options: &SearchOptions) -> u64 {
+    let mut hasher = FxHasher::default();
+    sorted_input.hash(&mut hasher);
+    options.search_type.hash(&mut hasher);
+    options.letter_classes.hash(&mut hasher);
+    options.word_to_include.hash(&mut hasher);
+    options.include_mode.hash(&mut hasher);
+    options.word_to_exclude.hash(&mut hasher);
+    options.exclude_mode.hash(&mut hasher);
+    hasher.finish()
 }
 
 fn encoded_letters_to_bloom_u32(input: &[u8]) -> u32 {
@@ -247,6 +633,16 @@ fn char_to_u8(c: char) -> u8 {
     encode_char(((base_char as u32) - 97) as u8, accent_index)
 }
 
+/** Lowercase words of the input, split on every character that is not a letter */
+fn input_words(input: &str) -> Vec<String> {
+    input
+        .to_lowercase()
+        .split(|c: char| !ALLOWED_CHARS.contains(c))
+        .filter(|w| !w.is_empty())
+        .map(String::from)
+        .collect()
+}
+
 fn str_to_u8(string: &str) -> Vec<u8> {
     string.chars().map(char_to_u8).collect()
 }
@@ -255,111 +651,412 @@ fn u8_to_str(u8: &[u8]) -> String {
     String::from_iter(u8.iter().map(|&encoded| u8_to_char(encoded)))
 }
 
-#[inline(always)]
-fn encoded_chars_equal(a: u8, b: u8, search_type: SearchType) -> bool {
-    if search_type == SearchType::EXACT {
-        a == b
-    } else {
-        (a & 0b11111000) == (b & 0b11111000)
-    }
-}
-
 impl Index {
     // construct the index from a jsonl file.
     // ASSUMES that the words are sorted by increasing length of letters
     fn new() -> Index {
-        let mut index = Index {
-            word_defs: vec![],
-            sorted_letters: vec![],
-            original_letters: vec![],
-            mean_word_size: 0.0,
-            tagging_stats: FxHashMap::default(),
-            pos_n_grams: FxHashMap::default(),
-        };
+        let mut index = Index::default();
 
         let vocab_lines: io::Lines<io::BufReader<File>> =
-            read_lines("data/words.jsonl").expect("Words file not found");
+            read_lines(WORDS_PATH).expect("Words file not found");
+        /* Homographs are merged into the first of their lines, with the readings and lemmas of all of them. Lines
+        have either a list of readings, or the POS tag and morphologies of a single one */
+        let mut entries: Vec<(String, WordReadings, f32)> = vec![];
+        let mut entry_lemmas: Vec<Vec<String>> = vec![];
+        let mut entry_positions: FxHashMap<String, usize> = FxHashMap::default();
         for line in vocab_lines {
-            if let Ok(word_def) = line {
-                let word_def: Value = serde_json::from_str(&word_def).unwrap();
-                let word = &word_def["word"].as_str().unwrap().to_lowercase();
-                let lengths = (index.original_letters.len(), index.sorted_letters.len());
-                if !word
-                    .chars()
-                    .all(|x| ALLOWED_CHARS.chars().any(|c| c == x))
-                {
-                    println!("{} not in character set: skipping", word);
-                    continue;
+            let Ok(word_def) = line else { continue };
+            let word_def: Value = serde_json::from_str(&word_def).unwrap();
+            let word = &word_def["word"].as_str().unwrap().to_lowercase();
+            if !word
+                .chars()
+                .all(|x| ALLOWED_CHARS.chars().any(|c| c == x))
+            {
+                println!("{} not in character set: skipping", word);
+                continue;
+            }
+            let readings: WordReadings = match word_def["readings"].as_array() {
+                Some(readings) => readings.iter().map(|r| Index::build_reading(r).unwrap()).collect(),
+                None => vec![Index::build_reading(&word_def).unwrap()],
+            };
+            let frequency = word_def["freq"].as_f64().unwrap_or(0.0) as f32;
+            let lemmas = Index::build_lemmas(&word_def, word);
+            match entry_positions.get(word) {
+                Some(&position) => {
+                    let entry = &mut entries[position];
+                    entry.1.extend(readings);
+                    entry.2 = entry.2.max(frequency);
+                    entry_lemmas[position].extend(lemmas);
+                }
+                None => {
+                    entry_positions.insert(word.clone(), entries.len());
+                    entries.push((word.clone(), readings, frequency));
+                    entry_lemmas.push(lemmas);
                 }
-                index.mean_word_size += word.len() as f32;
-                index.original_letters.extend_from_slice(&str_to_u8(word));
-                let sorted_range: Vec<u8> = index.original_letters
-                    [lengths.0..index.original_letters.len()]
-                    .iter()
-                    .cloned()
-                    .sorted()
-                    .collect();
-                let bloom_letters = encoded_letters_to_bloom_u32(&sorted_range);
-                index.sorted_letters.extend(sorted_range);
-                let new_word_def = Word {
-                    letters_original_range: lengths.0 as u32..index.original_letters.len() as u32,
-                    letters_sorted_range: lengths.1 as u32..index.sorted_letters.len() as u32,
-                    pos_tag: PosTag::from_str(word_def["pos"].as_str().unwrap()).unwrap(),
-                    morph_tags: Index::build_morph_tags(word_def["morph"].as_array().unwrap()),
-                    bloom_letters,
-                    // is_prio: PRIORITY_WORDS.iter().find(|&&x| x.eq(word)).is_some(),
-                };
-                index.word_defs.push(new_word_def);
             }
         }
-        index.mean_word_size /= index.word_defs.len() as f32;
+        for ((word, readings, frequency), lemmas) in entries.into_iter().zip(entry_lemmas) {
+            let word_id = index.word_defs.len() as WordId;
+            index.push_homographs(&word, readings, frequency);
+            for lemma in lemmas {
+                index.add_lemma_form(&lemma, word_id);
+            }
+        }
 
         let tagging_lines: io::Lines<io::BufReader<File>> =
-            read_lines("data/tagging_stats.jsonl").expect("Tagging stats file not found");
+            read_lines(TAGGING_STATS_PATH).expect("Tagging stats file not found");
         for line in tagging_lines {
-            if let Ok(stat) = line {
-                let stat: Value = serde_json::from_str(&stat).unwrap();
-                let tagging = stat["tagging"].as_array().unwrap();
-                let pos_1 = PosTag::from_str(tagging[0].as_str().unwrap()).unwrap();
-                let morph_1 = Morph::from_serde_map(tagging[1].as_object().unwrap());
-                let pos_2 = PosTag::from_str(tagging[2].as_str().unwrap()).unwrap();
-                let morph_2 = Morph::from_serde_map(tagging[3].as_object().unwrap());
-                let first = PosMorph {
-                    pos: pos_1,
-                    morph: morph_1,
-                };
-                let second = PosMorph {
-                    pos: pos_2,
-                    morph: morph_2,
-                };
-                let key = (first, second);
-                let occurences: f32 = (stat["nb"].as_u64().unwrap() as f32).sqrt();
-                index.tagging_stats.insert(key, occurences);
-            }
+            let Ok(stat) = line else { continue };
+            let stat: Value = serde_json::from_str(&stat).unwrap();
+            let tagging = stat["tagging"].as_array().unwrap();
+            let pos_1 = PosTag::from_str(tagging[0].as_str().unwrap()).unwrap();
+            let morph_1 = Morph::from_serde_map(tagging[1].as_object().unwrap());
+            let pos_2 = PosTag::from_str(tagging[2].as_str().unwrap()).unwrap();
+            let morph_2 = Morph::from_serde_map(tagging[3].as_object().unwrap());
+            let first = PosMorph {
+                pos: pos_1,
+                morph: morph_1,
+            };
+            let second = PosMorph {
+                pos: pos_2,
+                morph: morph_2,
+            };
+            let key = (first, second);
+            let occurences: f32 = (stat["nb"].as_u64().unwrap() as f32).sqrt();
+            index.tagging_stats.insert(key, occurences);
         }
 
         let pos_n_gram_lines: io::Lines<io::BufReader<File>> =
-            read_lines("data/pos_n_grams.jsonl").expect("pos_n_grams file not found");
+            read_lines(POS_N_GRAMS_PATH).expect("pos_n_grams file not found");
         for line in pos_n_gram_lines {
-            if let Ok(stat) = line {
-                let stat: Value = serde_json::from_str(&stat).unwrap();
-                let occurences: f32 = (stat["occ"].as_u64().unwrap() as f32).sqrt();
-                let mut ngram: Vec<Option<PosTag>> = stat["pos"]
-                    .as_array()
-                    .unwrap()
-                    .iter()
-                    .map(|x| Some(PosTag::from_str(x.as_str().unwrap()).unwrap()))
-                    .collect();
-                while ngram.len() < 4 {
-                    ngram.push(None);
-                }
-                let t: PosTagNGram = ngram.iter().cloned().collect_tuple().unwrap();
-                index.pos_n_grams.insert(t, occurences);
+            let Ok(stat) = line else { continue };
+            let stat: Value = serde_json::from_str(&stat).unwrap();
+            let occurences = stat["occ"].as_u64().unwrap() as f32;
+            let ngram: Vec<PosTag> = stat["pos"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|x| PosTag::from_str(x.as_str().unwrap()).unwrap())
+                .collect();
+            index.pos_n_grams.insert(&ngram, occurences);
+        }
+
+        match read_lines(WORD_BIGRAMS_PATH) {
+            Ok(word_bigram_lines) => index.load_word_bigrams(word_bigram_lines),
+            Err(_) => println!("{} not found, scoring with POS stats only", WORD_BIGRAMS_PATH),
+        }
+        match File::open(EMBEDDINGS_PATH) {
+            Ok(file) => {
+                index.embeddings = Embeddings::load(io::BufReader::new(file), &index.word_ids_by_string(), index.word_defs.len())
             }
+            Err(_) => println!("{} not found, not scoring by meaning", EMBEDDINGS_PATH),
         }
+        index.finalize();
+        index.calibrate();
         index
     }
 
+    /** Compute the normalisation of the scores of each scorer from the anagrams of `CALIBRATION_QUERIES` */
+    fn calibrate(&mut self) {
+        self.score_calibrations.clear();
+        for scorer in SCORERS {
+            let before = Instant::now();
+            let options = SearchOptions {
+                scorer: String::from(scorer.name()),
+                truncation_seed: Some(CALIBRATION_SEED),
+                ..SearchOptions::default()
+            };
+            let mut scores = vec![];
+            for query in CALIBRATION_QUERIES {
+                let Ok(result) = self.find_anagrams_reverse(String::from(query), &options) else { continue };
+                /* The single words are given the best score of the calibration */
+                let query_scores = result.anagrams.iter().filter(|a| a.0.contains(' ')).map(|a| a.1);
+                scores.extend(query_scores.sorted_by(|a, b| b.partial_cmp(a).unwrap()).take(CALIBRATION_TOP));
+            }
+            let calibration = ScoreCalibration::new(&scores);
+            println!("Scores of {} calibrated on {} anagrams in {:.2?}", scorer.name(), scores.len(), before.elapsed());
+            self.score_calibrations.insert(String::from(scorer.name()), calibration);
+        }
+    }
+
+    /** Normalisation of the scores of the scorer named `scorer`, `UNCALIBRATED` until `calibrate` is called */
+    fn score_calibration(&self, scorer: &str) -> &ScoreCalibration {
+        self.score_calibrations.get(scorer).unwrap_or(&UNCALIBRATED)
+    }
+
+    /** Append a word having a single reading, see `push_homographs` */
+    #[cfg(test)]
+    fn push_word(&mut self, word: &str, pos: PosTag, morph_tags: Vec<Morph>, frequency: f32) {
+        self.push_homographs(word, vec![(pos, morph_tags, None)], frequency);
+    }
+
+    /**
+     * Append a word to the index, interning its readings. The readings without probability are kept first, as the
+     * most probable. Words must be pushed by increasing number of letters, but for the overrides (see `WordDefs`), and
+     * `finalize` called once they all are.
+     */
+    fn push_homographs(&mut self, word: &str, readings: WordReadings, frequency: f32) {
+        let start = self.original_letters.len();
+        self.original_letters.extend_from_slice(&str_to_u8(word));
+        let sorted_range: Vec<u8> = self.original_letters[start..].iter().cloned().sorted().collect();
+        let bloom_letters = encoded_letters_to_bloom_u32(&sorted_range);
+        self.sorted_letters.extend(sorted_range);
+        let reading_set = self.intern_reading_set(self.word_defs.len() as WordId, readings);
+        self.word_defs.letters_ends.push(self.original_letters.len() as u32);
+        self.word_defs.bloom_letters.push(bloom_letters);
+        self.word_defs.reading_sets.push(reading_set);
+        self.word_defs.frequencies.push(frequency);
+        // is_prio: PRIORITY_WORDS.iter().find(|&&x| x.eq(word)).is_some(),
+    }
+
+    fn add_lemma_form(&mut self, lemma: &str, word: WordId) {
+        let forms = self.forms_by_lemma.entry(String::from(lemma)).or_default();
+        if !forms.contains(&word) {
+            forms.push(word);
+        }
+    }
+
+    /** Replace the readings of a word, `finalize` must be called again */
+    fn set_readings(&mut self, word: WordId, readings: WordReadings) {
+        self.word_defs.reading_sets[word as usize] = self.intern_reading_set(word, readings);
+    }
+
+    /** Intern the readings of a word, recording their probabilities, see `push_homographs` */
+    fn intern_reading_set(&mut self, word_id: WordId, readings: WordReadings) -> ReadingSetId {
+        let mut interned: Vec<(ReadingId, f32)> = vec![];
+        for (pos, morph_tags, probability) in readings {
+            let reading = self.intern_reading(pos, morph_tags);
+            let probability = probability.unwrap_or(f32::INFINITY);
+            match interned.iter_mut().find(|(r, _)| *r == reading) {
+                Some((_, existing)) => *existing = existing.max(probability),
+                None => interned.push((reading, probability)),
+            }
+        }
+        // Stable, so that the first line wins between readings without probability
+        interned.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        let best_probability = interned.iter().map(|r| r.1).find(|p| p.is_finite()).filter(|&p| p > 0.0).unwrap_or(1.0);
+        let relative_probabilities: Vec<f32> =
+            interned.iter().map(|r| if r.1.is_finite() { r.1 / best_probability } else { 1.0 }).collect();
+        if relative_probabilities.iter().any(|&p| p != 1.0) {
+            self.reading_probabilities.insert(word_id, relative_probabilities);
+        } else {
+            self.reading_probabilities.remove(&word_id);
+        }
+        let reading_set: Vec<ReadingId> = interned.into_iter().map(|r| r.0).collect();
+        if self.reading_set_ids.len() != self.reading_sets.len() {
+            self.reading_set_ids =
+                self.reading_sets.iter().enumerate().map(|(id, set)| (set.clone(), id as ReadingSetId)).collect();
+        }
+        if let Some(&id) = self.reading_set_ids.get(&reading_set) {
+            return id;
+        }
+        let id = ReadingSetId::try_from(self.reading_sets.len()).expect("Too many reading sets for ReadingSetId");
+        self.reading_set_ids.insert(reading_set.clone(), id);
+        self.reading_sets.push(reading_set);
+        id
+    }
+
+    /** There are only a few dozen readings, a linear search is fast enough */
+    fn intern_reading(&mut self, pos: PosTag, morph_tags: Vec<Morph>) -> ReadingId {
+        let morph_set = self.morph_sets.iter().position(|m| *m == morph_tags).unwrap_or_else(|| {
+            self.morph_sets.push(morph_tags);
+            self.morph_sets.len() - 1
+        });
+        let morph_set = MorphSetId::try_from(morph_set).expect("Too many morphology sets for MorphSetId");
+        let reading = Reading { pos, morph_set };
+        let position = self.readings.iter().position(|r| *r == reading).unwrap_or_else(|| {
+            self.readings.push(reading);
+            self.readings.len() - 1
+        });
+        ReadingId::try_from(position).expect("Too many readings for ReadingId")
+    }
+
+    fn word_ids_by_string(&self) -> FxHashMap<String, WordId> {
+        (0..self.word_defs.len() as WordId).map(|word| (u8_to_str(self.word_original_letters(word)), word)).collect()
+    }
+
+    /** Bigrams whose words are not in the index are skipped, as the lines that cannot be read, which are logged */
+    fn load_word_bigrams(&mut self, lines: impl Iterator<Item = io::Result<String>>) {
+        let word_ids = self.word_ids_by_string();
+        for (i, line) in lines.enumerate() {
+            let bigram = line.map_err(|e| e.to_string()).and_then(|line| Index::parse_word_bigram(&line));
+            let (first, second, occurences) = match bigram {
+                Ok(bigram) => bigram,
+                Err(msg) => {
+                    println!("{}:{}: bigram skipped, {}", WORD_BIGRAMS_PATH, i + 1, msg);
+                    continue;
+                }
+            };
+            if let (Some(&first), Some(&second)) = (word_ids.get(&first), word_ids.get(&second)) {
+                self.word_bigrams.insert((first, second), occurences);
+            }
+        }
+    }
+
+    /** Words of a line of the word bigrams file, and the square root of their occurences as the tagging stats */
+    fn parse_word_bigram(line: &str) -> Result<(String, String, f32), String> {
+        let stat: Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+        let words = stat["2-gram"].as_array().map(|words| words.iter().filter_map(Value::as_str).collect_vec());
+        let Some([first, second]) = words.as_deref() else {
+            return Err(String::from("2-gram should be a pair of words"));
+        };
+        let occurences = stat["occ"].as_u64().ok_or("missing occ")?;
+        Ok((String::from(*first), String::from(*second), (occurences as f32).sqrt()))
+    }
+
+    /** Compute what depends on both the words and the stats */
+    fn finalize(&mut self) {
+        self.mean_word_size = self.original_letters.len() as f32 / self.word_defs.len() as f32;
+        self.pos_n_grams.finalize();
+        self.reading_pair_stats = self.compute_reading_pair_stats();
+        self.pair_scores = PairScores::new(self);
+    }
+
+    /** Best tagging stat among all the morphologies of both readings, for each pair of readings */
+    fn compute_reading_pair_stats(&self) -> Vec<f32> {
+        let mut stats = Vec::with_capacity(self.readings.len() * self.readings.len());
+        for first in &self.readings {
+            for second in &self.readings {
+                let mut best_stat = 0.0;
+                for first_morph in &self.morph_sets[first.morph_set as usize] {
+                    for second_morph in &self.morph_sets[second.morph_set as usize] {
+                        let key = (
+                            PosMorph { morph: first_morph.tagging_stats_key(), pos: first.pos },
+                            PosMorph { morph: second_morph.tagging_stats_key(), pos: second.pos },
+                        );
+                        if let Some(&stat) = self.tagging_stats.get(&key) {
+                            best_stat = stat.max(best_stat);
+                        }
+                    }
+                }
+                stats.push(best_stat);
+            }
+        }
+        stats
+    }
+
+    /** Best tagging stat of two readings, see `reading_pair_stats` */
+    fn reading_pair_stat(&self, first: ReadingId, second: ReadingId) -> f32 {
+        self.reading_pair_stats[first as usize * self.readings.len() + second as usize]
+    }
+
+    /** Best score of the readings of two words, see `PairScores` */
+    #[inline(always)]
+    fn reading_pair_score(&self, first: WordId, second: WordId) -> f32 {
+        self.pair_scores.of_reading_sets(self.word_defs.reading_sets[first as usize], self.word_readings(second))
+    }
+
+    #[inline(always)]
+    fn pair_score_of_readings(&self, first: ReadingId, second: ReadingId) -> f32 {
+        self.pair_scores.of_readings(first, second)
+    }
+
+    #[inline(always)]
+    fn word_bigram_score(&self, first: WordId, second: WordId) -> Option<f32> {
+        if self.word_bigrams.is_empty() {
+            return None;
+        }
+        self.word_bigrams.get(&(first, second)).copied()
+    }
+
+    /** Readings of the word, the main one first */
+    fn word_readings(&self, word: WordId) -> &[ReadingId] {
+        &self.reading_sets[self.word_defs.reading_sets[word as usize] as usize]
+    }
+
+    /** Probability of a reading of the word, relative to its main reading */
+    fn reading_probability(&self, word: WordId, reading: ReadingId) -> f32 {
+        match self.reading_probabilities.get(&word) {
+            Some(probabilities) => {
+                let position = self.word_readings(word).iter().position(|&r| r == reading).unwrap();
+                probabilities[position]
+            }
+            None => 1.0,
+        }
+    }
+
+    /** POS tag of the main reading of the word */
+    fn word_pos(&self, word: WordId) -> PosTag {
+        self.reading_pos(self.word_readings(word)[0])
+    }
+
+    fn reading_pos(&self, reading: ReadingId) -> PosTag {
+        self.readings[reading as usize].pos
+    }
+
+    fn reading_morphs(&self, reading: ReadingId) -> &[Morph] {
+        &self.morph_sets[self.readings[reading as usize].morph_set as usize]
+    }
+
+    fn word_sorted_letters(&self, word: WordId) -> &[u8] {
+        &self.sorted_letters[self.word_defs.letters_range(word)]
+    }
+
+    fn word_original_letters(&self, word: WordId) -> &[u8] {
+        &self.original_letters[self.word_defs.letters_range(word)]
+    }
+
+    /** Files the index is built from, the optional ones only when they exist */
+    fn source_paths() -> Vec<&'static str> {
+        let mut paths = vec![WORDS_PATH, TAGGING_STATS_PATH, POS_N_GRAMS_PATH];
+        for optional_path in [WORD_BIGRAMS_PATH, EMBEDDINGS_PATH] {
+            if Path::new(optional_path).exists() {
+                paths.push(optional_path);
+            }
+        }
+        paths
+    }
+
+    /** Load the cached binary index when it is up to date, build the index from the JSONL files otherwise */
+    fn load() -> Index {
+        let source_checksum = index_cache::source_checksum(&Index::source_paths());
+        if source_checksum.is_none() {
+            println!("Source files not found, cannot check if {} is stale", INDEX_CACHE_PATH);
+        }
+        match index_cache::load(INDEX_CACHE_PATH, source_checksum) {
+            Ok(index) => index,
+            Err(msg) => {
+                println!("Not using {}: {}, building index from JSONL files", INDEX_CACHE_PATH, msg);
+                Index::new()
+            }
+        }
+    }
+
+    /** Build the index from the JSONL files and write it to the cached binary index */
+    fn build_file() -> io::Result<()> {
+        let source_checksum = index_cache::source_checksum(&Index::source_paths())
+            .expect("Source files not found");
+        let index = Index::new();
+        index_cache::save(&index, INDEX_CACHE_PATH, source_checksum)?;
+        println!("Index written to {}", INDEX_CACHE_PATH);
+        print!("{}", index);
+        Ok(())
+    }
+
+    /** A reading of the words file: POS tag, morphologies and optional probability */
+    fn build_reading(reading: &Value) -> Result<(PosTag, Vec<Morph>, Option<f32>), String> {
+        let pos = reading["pos"].as_str().ok_or("missing pos")?;
+        let pos = PosTag::from_str(pos).map_err(|_| format!("unknown pos {}", pos))?;
+        let morph = reading["morph"].as_array().ok_or("missing morph")?;
+        if morph.iter().any(|m| !m.is_object()) {
+            return Err(String::from("morph should be a list of objects"));
+        }
+        Ok((pos, Index::build_morph_tags(morph), reading["prob"].as_f64().map(|p| p as f32)))
+    }
+
+    /** Lemmas of a line of the words file, given as a string or a list of them, the word itself when none is */
+    fn build_lemmas(word_def: &Value, word: &str) -> Vec<String> {
+        let lemmas: Vec<String> = match &word_def["lemma"] {
+            Value::String(lemma) => vec![lemma.to_lowercase()],
+            Value::Array(lemmas) => lemmas.iter().filter_map(Value::as_str).map(str::to_lowercase).collect(),
+            _ => vec![],
+        };
+        if lemmas.is_empty() {
+            return vec![String::from(word)];
+        }
+        lemmas
+    }
+
     fn build_morph_tags(morph: &[Value]) -> Vec<Morph> {
         morph
             .iter()
@@ -367,10 +1064,11 @@ impl Index {
             .collect()
     }
 
+    /** Whether all the `searched` letters are in the pool, both being sorted by key, see `LetterClasses` */
     fn check_contains_all_letters(
         letter_pool: &[u8],
         searched: &[u8],
-        search_type: SearchType,
+        classes: &LetterClasses,
     ) -> bool {
         let lengths = (letter_pool.len(), searched.len()); // pool, searched
         if lengths.1 > lengths.0 {
@@ -378,7 +1076,7 @@ impl Index {
         }
         let mut indexes = (0, 0); // pool, searched
         while indexes.0 < lengths.0 && indexes.1 < lengths.1 {
-            if encoded_chars_equal(searched[indexes.1], letter_pool[indexes.0], search_type) {
+            if classes.equal(searched[indexes.1], letter_pool[indexes.0]) {
                 indexes.1 += 1;
             } 
             indexes.0 += 1;
@@ -392,7 +1090,7 @@ impl Index {
     fn new_vec_removed_letters(
         original: &[u8],
         matched_word: &[u8],
-        search_type: SearchType,
+        classes: &LetterClasses,
     ) -> Letters {
         let lengths = (original.len(), matched_word.len()); // pool, searched
         let mut remaining: Vec<u8> = Vec::with_capacity(lengths.0 - lengths.1);
@@ -401,8 +1099,7 @@ impl Index {
             if indexes.1 == lengths.1 {
                 remaining.push(original[indexes.0]);
                 indexes.0 += 1;
-            } else if encoded_chars_equal(matched_word[indexes.1], original[indexes.0], search_type)
-            {
+            } else if classes.equal(matched_word[indexes.1], original[indexes.0]) {
                 indexes.0 += 1;
                 indexes.1 += 1;
             } else if matched_word[indexes.1] > original[indexes.0] {
@@ -424,67 +1121,226 @@ impl Index {
     }
 
     /**
-     * Get matchable words, always including words containing the most letters
-     * Returns true alongside vector if it was truncated randomly
+     * Semantic measures of an anagram of several words. Single words are left out, their score being the highest
+     * possible already.
+     */
+    fn semantic_score(&self, words: &[WordId], input_vectors: &[u32], preference: SemanticPreference) -> Option<SemanticScore> {
+        if self.embeddings.is_empty() || words.len() < 2 {
+            return None;
+        }
+        self.embeddings.semantic_score(words, input_vectors, preference)
+    }
+
+    /**
+     * Geometric mean of `1 + frequency` of the words, raised to `weight`: a weight of 0 ignores how common the words
+     * are. Single words are left out, as for `semantic_score`.
      */
-    fn get_matchable_words(&self, input_letters: &[u8], search_type: SearchType, word_to_include: &[u8],) -> Result<(Vec<&Word>, bool), String> {
-        let mut words: Vec<&Word> = self.word_defs
+    fn commonness_factor(&self, words: &[WordId], weight: f32) -> f32 {
+        if words.len() < 2 {
+            return 1.0;
+        }
+        let log_sum: f32 = words.iter().map(|&w| self.word_defs.frequencies[w as usize].ln_1p()).sum();
+        (weight * log_sum / words.len() as f32).exp()
+    }
+
+    /** Words with their readings, as chosen by `best_readings` */
+    fn tokens(&self, ordered_words: &[WordId]) -> Vec<Token> {
+        let readings = best_readings(ordered_words, self);
+        ordered_words
             .iter()
-            .filter(| w| {
-                Index::check_contains_all_letters(
-                    input_letters,
-                    &self.sorted_letters[w.letters_sorted_range.start as usize
-                        ..w.letters_sorted_range.end as usize],
-                    search_type,
-                )
+            .enumerate()
+            .map(|(i, &word)| {
+                let mut morphs = self.reading_morphs(readings[i]).iter().map(Morph::features).collect_vec();
+                let best = self.best_morph(&readings, i);
+                let morph = if morphs.is_empty() { BTreeMap::new() } else { morphs.remove(best) };
+                Token {
+                    surface: u8_to_str(self.word_original_letters(word)),
+                    pos: self.reading_pos(readings[i]),
+                    morph,
+                    other_morphs: morphs,
+                    word_id: word,
+                }
+            })
+            .collect()
+    }
+
+    /**
+     * Position of the morphology of `readings[i]` fitting the neighbouring readings best: the one with the best pair
+     * scores with any morphology of the previous and next readings, see `scorer::morph_pair_score`. The first one on
+     * ties, so the first one if none of them was seen.
+     */
+    fn best_morph(&self, readings: &[ReadingId], i: usize) -> usize {
+        let pos_morph = |reading: ReadingId, morph: &Morph| PosMorph { morph: *morph, pos: self.reading_pos(reading) };
+        /* Best over all the morphologies of a reading whose morphology is None */
+        let best_pair_score = |first, first_morph: Option<&Morph>, second, second_morph: Option<&Morph>| {
+            let firsts = first_morph.map_or(self.reading_morphs(first), std::slice::from_ref);
+            let seconds = second_morph.map_or(self.reading_morphs(second), std::slice::from_ref);
+            firsts
+                .iter()
+                .cartesian_product(seconds)
+                .map(|(a, b)| morph_pair_score(self, &pos_morph(first, a), &pos_morph(second, b)))
+                .fold(0.0, f32::max)
+        };
+        let mut best = (0, f32::MIN);
+        for (m, morph) in self.reading_morphs(readings[i]).iter().enumerate() {
+            let mut score = 0.0;
+            if i > 0 {
+                score += best_pair_score(readings[i - 1], None, readings[i], Some(morph));
+            }
+            if i + 1 < readings.len() {
+                score += best_pair_score(readings[i], Some(morph), readings[i + 1], None);
+            }
+            if score > best.1 {
+                best = (m, score);
+            }
+        }
+        best.0
+    }
+
+    /** Number of letters of the words not keeping the accent of the sorted input letters */
+    fn accent_substitutions(&self, words: &[WordId], input_letters: &[u8]) -> usize {
+        let letters: Letters = words.iter().flat_map(|&w| self.word_sorted_letters(w)).copied().sorted().collect();
+        accent_substitutions(input_letters, &letters)
+    }
+
+    /** All the words of the index that can be written with the sorted input letters, by increasing length */
+    fn filter_matchable_words(&self, input_letters: &[u8], classes: &LetterClasses) -> Vec<WordId> {
+        let input_bloom = encoded_letters_to_bloom_u32(input_letters);
+        let input_keys = classes.sorted_keys(input_letters);
+        let mut buffer = Letters::new();
+        let mut words: Vec<WordId> = (0..self.word_defs.len() as WordId)
+            .filter(|&w| {
+                let bloom_letters = self.word_defs.bloom_letters[w as usize];
+                (input_bloom & bloom_letters) == bloom_letters && Index::check_contains_all_letters(
+                    &input_keys,
+                    classes.sorted_by_key(self.word_sorted_letters(w), &mut buffer),
+                    classes,
+                ) && !self.removed_words.contains(&w)
                 
             })
             .collect();
+        // Words added by the overrides are at the end, see `WordDefs`. Stable and linear on the sorted other words
+        words.sort_by_key(|&w| self.word_defs.nb_letters(w));
+        words
+    }
 
-        // We will find the word, put it at the end of the array, and during the finding of anagrams,
-        // this will remain the only root of the search tree
-        if word_to_include.len() > 0 {
-            let index = words
-            .iter()
-            .find_position(|w| {
-                let searched = &self.original_letters[w.letters_sorted_range.start as usize..w.letters_sorted_range.end as usize];
-                word_to_include.len() == searched.len() && searched.iter().zip(word_to_include.iter())
-                .all(|(a, b)| encoded_chars_equal(*a, *b, search_type))
-            });
-            if index.is_some() {
-                // println!("Word found! at index {}", index.unwrap().0);
-                let removed = words.remove(index.unwrap().0);
-                words.push(removed);
-            } else {
-                return Err(String::from("Le mot à inclure n'est pas contenu dans l'index"))
+    /**
+     * The words among `words` matching a word given in a query: the words with the same letters according to
+     * `classes`, or the forms of the lemma
+     */
+    fn words_matching(&self, word: &str, mode: MatchMode, classes: &LetterClasses, words: &[WordId]) -> Vec<WordId> {
+        let word = word.to_lowercase();
+        match mode {
+            MatchMode::Lemma => {
+                let Some(forms) = self.forms_by_lemma.get(&word) else {
+                    return vec![];
+                };
+                let forms: FxHashSet<WordId> = forms.iter().copied().collect();
+                words.iter().copied().filter(|w| forms.contains(w)).collect()
+            }
+            MatchMode::Word => {
+                let letters: Letters = word.chars().filter(|x| ALLOWED_CHARS.contains(*x)).map(char_to_u8).collect();
+                words.iter()
+                    .copied()
+                    .filter(|&w| {
+                        let searched = self.word_original_letters(w);
+                        letters.len() == searched.len()
+                            && searched.iter().zip(letters.iter()).all(|(&a, &b)| classes.equal(a, b))
+                    })
+                    .collect()
             }
-            return Ok((words, false));
         }
-        if words.len() <= MAX_MATCHABLE_WORDS {
-            return Ok((words, false));
+    }
+
+    /**
+     * Get matchable words among the words fitting the input, always including words containing the most letters,
+     * without the `excluded` ones. Returns true alongside vector if it was truncated at random, drawn from `seed`. The truncation size
+     * depends on the estimated cost of the query, and queries too expensive even after truncation are rejected.
+     * The `included` words are put at the end, their number being returned as well: they are the roots of the search
+     * tree, which makes it cheaper with few of them. They are never truncated. With `prefer_exact`, the words kept at
+     * random are drawn from the words keeping the accents of the input first, as the anagrams made of them are favoured.
+     */
+    fn get_matchable_words(
+        &self,
+        input_letters: &[u8],
+        words: Vec<WordId>,
+        included: &[WordId],
+        excluded: &[WordId],
+        prefer_exact: bool,
+        seed: u64,
+    ) -> Result<(Vec<WordId>, bool, usize), String> {
+        let excluded: FxHashSet<WordId> = excluded.iter().copied().collect();
+        let included: FxHashSet<WordId> = included.iter().copied().collect();
+        let (words, roots): (Vec<WordId>, Vec<WordId>) =
+            words.into_iter().filter(|w| !excluded.contains(w)).partition(|w| !included.contains(w));
+        if !included.is_empty() && roots.is_empty() {
+            return Err(String::from("Le mot à inclure n'est pas contenu dans l'index"));
+        }
+        let nb_roots = roots.len();
+
+        let word_sizes = words.iter().chain(&roots).map(|&w| self.word_defs.nb_letters(w)).collect_vec();
+        let mut cost = QueryCost::new(input_letters.len(), word_sizes.into_iter());
+        if nb_roots > 0 {
+            cost = cost.with_roots(nb_roots);
         }
+        let (nb_words, nb_big_words) = match cost.plan(LATENCY_BUDGET_MS) {
+            QueryPlan::Complete => return Ok(([words, roots].concat(), false, nb_roots)),
+            QueryPlan::Truncated { nb_words, nb_big_words } => (nb_words, nb_big_words),
+            QueryPlan::Rejected { estimated_ms } => {
+                println!("Rejected query of {} letters, estimated to {:.0}ms", input_letters.len(), estimated_ms);
+                if nb_roots > 0 {
+                    return Err(String::from("Cette expression est trop complexe avec ce mot à inclure, essayez avec moins de lettres ou un mot ayant moins de formes"));
+                }
+                return Err(String::from("Cette expression est trop complexe, essayez avec moins de lettres ou en incluant un mot"));
+            }
+        };
+        // The words to include are planned as well
+        let nb_words = nb_words.saturating_sub(nb_roots);
         // println!("{} words before truncate", words.len());
-        /* Always include NB_BIG_WORDS_INCLUDED bigger words */
-        let mut rng = thread_rng();
+        /* Always include the nb_big_words bigger words */
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut result = Vec::new();
 
-        let suffix_size = NB_BIG_WORDS_INCLUDED.min(words.len());
+        let suffix_size = nb_big_words.min(words.len());
         let start_suffix = words.len().saturating_sub(suffix_size);
         let suffix = &words[start_suffix..];
         result.extend_from_slice(suffix);
         let remaining = &words[..start_suffix];
-        let additional_size = (MAX_MATCHABLE_WORDS - suffix_size).min(remaining.len());
-        let additional_elements = remaining.choose_multiple(&mut rng, additional_size);
-        result.extend(additional_elements.cloned());
-        result.sort_by(|a, b| {
-            let length_a: u32 = a.letters_sorted_range.end - a.letters_sorted_range.start ;
-            let length_b: u32 = b.letters_sorted_range.end - b.letters_sorted_range.start;
-            return length_a.partial_cmp(&length_b).unwrap();
-        });
-        Ok((result, true))
+        let additional_size = nb_words.saturating_sub(suffix_size).min(remaining.len());
+        if prefer_exact {
+            let (exact, others): (Vec<WordId>, Vec<WordId>) =
+                remaining.iter().partition(|&&w| accent_substitutions(input_letters, self.word_sorted_letters(w)) == 0);
+            result.extend(exact.choose_multiple(&mut rng, additional_size));
+            result.extend(others.choose_multiple(&mut rng, additional_size.saturating_sub(exact.len())));
+        } else {
+            let additional_elements = remaining.choose_multiple(&mut rng, additional_size);
+            result.extend(additional_elements.cloned());
+        }
+        result.sort_by_key(|&w| self.word_defs.nb_letters(w));
+        result.extend(roots);
+        Ok((result, true, nb_roots))
 
     }
 
+    /** Forms of the lemma that can be written with the letters of `input`, all of them if it has no letter */
+    fn lemma_forms(&self, lemma: &str, input: String, classes: &LetterClasses) -> Result<LemmaForms, String> {
+        let Some(forms) = self.forms_by_lemma.get(&lemma.to_lowercase()) else {
+            return Err(format!("Lemme inconnu : {}", lemma));
+        };
+        let sorted_input = classes.sorted_keys(&self.process_input(input));
+        let forms = forms
+            .iter()
+            .copied()
+            .filter(|w| !self.removed_words.contains(w))
+            .filter(|&w| {
+                sorted_input.is_empty()
+                    || Index::check_contains_all_letters(&sorted_input, &classes.sorted_keys(self.word_sorted_letters(w)), classes)
+            })
+            .map(|w| u8_to_str(self.word_original_letters(w)))
+            .collect();
+        Ok(LemmaForms { lemma: lemma.to_lowercase(), forms })
+    }
+
     /**
      * This algorithm is similar to the construction of a powerset of all words containing provided letters. See https://en.wikipedia.org/wiki/Power_set
      * The size of a powerset is 2^n. Of course this size is never reached since we remove letters from candidates as we get going.
@@ -492,38 +1348,49 @@ impl Index {
      * TODO:
      * - If we have a lot of words matching letters, rank them by occurence in some reference corpora 
      */
-    fn find_anagrams_reverse(&self, input: String, search_type: SearchType, word_to_include: String) -> Result<AnagramResult, String> {
-        let max_cand_to_find = 10000;
+    fn find_anagrams_reverse(&self, input: String, options: &SearchOptions) -> Result<AnagramResult, String> {
+        let classes = LetterClasses::new(options.search_type, &options.letter_classes)?;
+        let scorer = scorer_by_name(&options.scorer).ok_or_else(|| format!("Classement inconnu : {}", options.scorer))?;
         let mut nb_found = 0;
-        let sorted_input = self.process_input(input);
-        if sorted_input.len() > MAX_QUERY_LETTERS {
-            return Err(format!("Trop de lettres ({}, le maximum est {})", sorted_input.len(), MAX_QUERY_LETTERS));
-        }
+        let input_words = input_words(&input);
+        let input_vectors: Vec<u32> = input_words.iter().filter_map(|w| self.embeddings.vector_of(w)).collect();
+        let input_words_letters: Vec<Letters> = input_words.iter().map(|w| str_to_u8(w)).collect();
+        let sorted_input = self.process_input(input.clone());
+        let input_keys = classes.sorted_keys(&sorted_input);
         let mut candidates: Vec<Matching> = vec![];
         let mut enough_found = false;
-        let mut mode_include = false;
-        let mut processed_to_include : Vec<u8> = vec![];
-        if word_to_include.len() > 0 {
-            mode_include = true;
-            processed_to_include = word_to_include.to_lowercase().chars()
-            .filter(|x| ALLOWED_CHARS.chars().any(|c| c == *x))
-            .map(char_to_u8)
-            .collect()
-        }
+        let fitting_words = self.filter_matchable_words(&sorted_input, &classes);
+        let included = if options.word_to_include.is_empty() {
+            vec![]
+        } else {
+            let included = self.words_matching(&options.word_to_include, options.include_mode, &classes, &fitting_words);
+            if included.is_empty() {
+                return Err(String::from("Le mot à inclure n'est pas contenu dans l'index"));
+            }
+            included
+        };
+        let mode_include = !included.is_empty();
+        let excluded = if options.word_to_exclude.is_empty() {
+            vec![]
+        } else {
+            self.words_matching(&options.word_to_exclude, options.exclude_mode, &classes, &fitting_words)
+        };
         // let start = Instant::now();
 
-        let matchable_words_res = self.get_matchable_words(&sorted_input, search_type, &processed_to_include);
-        if matchable_words_res.is_err() {
-            return Err(matchable_words_res.unwrap_err());
-        }
-        let (matchable_words, was_truncated) = matchable_words_res.unwrap();
+        let seed = options.truncation_seed.unwrap_or_else(|| truncation_seed(&sorted_input, options));
+        let prefer_exact = options.accent_bonus > 1.0;
+        let (matchable_words, was_truncated, nb_included) =
+            self.get_matchable_words(&sorted_input, fitting_words, &included, &excluded, prefer_exact, seed)?;
         let nb_matchable_words = matchable_words.len();
         // println!("{} matchabled words", matchable_words.len());
-        for (index, word) in matchable_words.iter().enumerate().rev() {
-            // println!("{}, {}", index, u8_to_str(&self.sorted_letters[word.letters_sorted_range.start as usize..word.letters_sorted_range.end as usize]));
-            let searched_word_letters = &self.sorted_letters
-                [word.letters_sorted_range.start as usize..word.letters_sorted_range.end as usize];
-            let nb_cand = candidates.len();
+        let mut buffer = Letters::new();
+        for (index, &word) in matchable_words.iter().enumerate().rev() {
+            // println!("{}, {}", index, u8_to_str(self.word_sorted_letters(word)));
+            let searched_word_letters = classes.sorted_by_key(self.word_sorted_letters(word), &mut buffer);
+            let word_bloom_letters = self.word_defs.bloom_letters[word as usize];
+            /* A word to include only starts candidates, anagrams contain a single one of them */
+            let is_included = mode_include && index >= nb_matchable_words - nb_included;
+            let nb_cand = if is_included { 0 } else { candidates.len() };
             /* Search new candidates among current ones */
             for cand_index in 0..nb_cand {
                 let candidate: &Matching = &candidates[cand_index];
@@ -531,13 +1398,13 @@ impl Index {
                     continue;
                 }
                 let bloom_ok: bool =
-                    (candidate.bloom_letters & word.bloom_letters) == word.bloom_letters;
+                    (candidate.bloom_letters & word_bloom_letters) == word_bloom_letters;
                 /* Create new candidate with the matching letters removed from the pool */
                 let check_pass = candidate.matched_size < MAX_EXPR_SIZE as u8 && bloom_ok
                     && Index::check_contains_all_letters(
                         &candidate.letter_pool,
                         searched_word_letters,
-                        search_type,
+                        &classes,
                     );
                 /* Create new candidate with the matching letters removed from the pool */
                 if check_pass {
@@ -545,9 +1412,9 @@ impl Index {
                     remove_elems(
                         &mut new_cand.letter_pool,
                         searched_word_letters,
-                        search_type,
+                        &classes,
                     );
-                    new_cand.is_complete = new_cand.letter_pool.len() == 0;
+                    new_cand.is_complete = new_cand.letter_pool.is_empty();
                     new_cand.bloom_letters = encoded_letters_to_bloom_u32(&new_cand.letter_pool);
                     new_cand.matched[new_cand.matched_size as usize] = index as u16;
                     new_cand.matched_size += 1;
@@ -555,7 +1422,7 @@ impl Index {
                         nb_found += 1;
                         // new_cand.best_permutation(self);
                     }
-                    if nb_found == max_cand_to_find {
+                    if nb_found == MAX_COMPLETE_CANDIDATES {
                         enough_found = true;
                         break;
                     }
@@ -565,19 +1432,19 @@ impl Index {
             if enough_found {
                 break;
             }
-            let should_add_new_cand = !mode_include || index == nb_matchable_words - 1;
+            let should_add_new_cand = !mode_include || is_included;
             /* Find new candidates from scratch */
             if should_add_new_cand && Index::check_contains_all_letters(
-                    &sorted_input,
+                    &input_keys,
                     searched_word_letters,
-                    search_type,
+                    &classes,
                 )
             {
                 // remove letters from original pool
                 let remaining_letters = Index::new_vec_removed_letters(
-                    &sorted_input,
+                    &input_keys,
                     searched_word_letters,
-                    search_type,
+                    &classes,
                 );
                 let length = remaining_letters.len();
                 let bloom_letters = encoded_letters_to_bloom_u32(&remaining_letters);
@@ -598,16 +1465,109 @@ impl Index {
         }
         
         // let start_scoring = Instant::now();
-        let mut str_with_scores: Vec<(String, f32)> = candidates
+        let nb_orderings = options.orderings_per_result.clamp(1, MAX_ORDERINGS_PER_RESULT);
+        let reuse_penalty = options.reuse_penalty.clamp(0.0, 1.0);
+        let commonness_weight = options.commonness_weight.max(0.0);
+        let accent_bonus = options.accent_bonus.max(1.0);
+        let mut anagrams: Vec<ScoredAnagram> = candidates
             .into_par_iter()
             .filter(|m| m.is_complete)
-            .map(|m| m.best_permutation(&self, &matchable_words))
+            .map(|m| m.best_permutations(self, &matchable_words, scorer, nb_orderings, options.grammatical_only))
+            .filter(|o| !o.is_empty())
+            .filter_map(|mut orderings| {
+                let reuse = input_reuse(&orderings[0].0, self, &input_words_letters);
+                if options.forbid_reuse && !reuse.is_empty() {
+                    return None;
+                }
+                let semantics = self.semantic_score(&orderings[0].0, &input_vectors, options.semantic);
+                let commonness = self.commonness_factor(&orderings[0].0, commonness_weight);
+                let substitutions = if accent_bonus > 1.0 || options.max_accent_substitutions.is_some() {
+                    self.accent_substitutions(&orderings[0].0, &sorted_input)
+                } else {
+                    0
+                };
+                if options.max_accent_substitutions.is_some_and(|max| substitutions > max) {
+                    return None;
+                }
+                /* The bonus goes to the anagrams keeping the accents, the others are divided by it */
+                let accents = accent_bonus.powi(-(substitutions as i32));
+                let query_factor =
+                    reuse.factor(reuse_penalty) * semantics.map_or(1.0, |s| s.factor) * commonness * accents;
+                orderings.iter_mut().for_each(|(_, score)| *score *= query_factor);
+                let text = words_to_string(&orderings[0].0, self);
+                Some(ScoredAnagram { text, orderings, semantics, reuse, commonness, accents, query_factor })
+            })
             .collect();
-        str_with_scores.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        anagrams.sort_by(|a, b| b.orderings[0].1.partial_cmp(&a.orderings[0].1).unwrap());
+        /* Different words can be written the same, only the best scored is kept */
+        let mut seen_texts: FxHashSet<String> = FxHashSet::default();
+        anagrams.retain(|anagram| seen_texts.insert(anagram.text.clone()));
+        let order = diversified_order(
+            &anagrams.iter().map(|a| (&a.orderings[0].0[..], a.orderings[0].1)).collect_vec(),
+            self,
+            options.diversity,
+            options.max_word_repeats,
+        );
+        let anagrams = reordered(anagrams, order);
+        let order = sorted_order(
+            &anagrams.iter().map(|a| &a.orderings[0].0[..]).collect_vec(),
+            self,
+            options.sort_by,
+            options.group_by,
+        );
+        let anagrams = reordered(anagrams, order);
+        let total = anagrams.len();
+        let anagrams: Vec<ScoredAnagram> =
+            anagrams.into_iter().skip(options.offset).take(options.limit.unwrap_or(usize::MAX)).collect();
+        let groups = match options.group_by {
+            GroupBy::None => vec![],
+            GroupBy::MainWord => {
+                anagrams.iter().map(|a| u8_to_str(self.word_original_letters(main_word(&a.orderings[0].0, self)))).collect()
+            }
+        };
         // println!("Time to find best permutations: {:.2?}", start_scoring.elapsed());
-        // println!("Found {} anagrams", str_with_scores.len());
-
-        Ok(AnagramResult { anagrams: str_with_scores, was_truncated})
+        // println!("Found {} anagrams", orderings.len());
+
+        let mut details = vec![];
+        let any_detail = options.explain || options.report_substitutions || options.map_letters || options.tokens;
+        if nb_orderings > 1 || any_detail {
+            details = anagrams
+                .par_iter()
+                .map(|anagram| {
+                    let o = &anagram.orderings;
+                    let mut detail = AnagramDetails::default();
+                    if nb_orderings > 1 {
+                        detail.orderings = o.iter().map(|(words, score)| (words_to_string(words, self), *score)).collect();
+                    }
+                    if options.explain {
+                        detail.explanation =
+                            scorer.explain(&o[0].0, self).map(|b| b.with_query_factor(anagram.query_factor));
+                        detail.semantics = anagram.semantics;
+                        detail.reuse = Some(anagram.reuse.clone()).filter(|r| !r.is_empty());
+                        detail.commonness = Some(anagram.commonness);
+                        detail.accents = Some(anagram.accents).filter(|_| accent_bonus > 1.0);
+                    }
+                    if options.report_substitutions {
+                        detail.substitutions = substituted_letters(&sorted_input, &anagram.text, &classes);
+                    }
+                    if options.map_letters {
+                        detail.letter_mapping = letter_mapping(&input, &anagram.text, &classes);
+                    }
+                    if options.tokens {
+                        detail.tokens = self.tokens(&o[0].0);
+                    }
+                    detail
+                })
+                .collect();
+        }
+        let anagrams = anagrams
+            .into_iter()
+            .map(|anagram| {
+                let score = anagram.orderings[0].1;
+                (anagram.text, score, self.score_calibration(scorer.name()).normalize(score))
+            })
+            .collect();
+        Ok(AnagramResult { anagrams, total, groups, was_truncated, details })
     }
 
 }
@@ -615,9 +1575,9 @@ impl Index {
 impl fmt::Display for Index {
     // This trait requires `fmt` with this exact signature.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // for word in &self.word_defs {
-        //     let original = &self.original_letters[word.letters_original_range.start as usize..word.letters_original_range.end as usize];
-        //     let sorted = &self.sorted_letters[word.letters_sorted_range.start as usize..word.letters_sorted_range.end as usize];
+        // for word in 0..self.word_defs.len() as WordId {
+        //     let original = self.word_original_letters(word);
+        //     let sorted = self.word_sorted_letters(word);
         //     writeln!(f, "{}, sorted : {}", self.u8_to_str(original), self.u8_to_str(sorted))?
         // }
         writeln!(
@@ -637,23 +1597,19 @@ impl fmt::Display for Index {
         //     }
         // }
 
+        writeln!(
+            f,
+            "{} readings, {} reading sets, {} morphology sets",
+            self.readings.len(),
+            self.reading_sets.len(),
+            self.morph_sets.len()
+        )?;
         writeln!(f, "Mean letter count per word: {}", self.mean_word_size)?;
+        writeln!(f, "{} word bigrams, {} word vectors", self.word_bigrams.len(), self.embeddings.nb_vectors())?;
         Ok(())
     }
 }
 
-fn pos_tuple_from_words(words_indexes: &Vec<&u16>, matchable_words: &[&Word]) -> PosTagNGram {
-    let mut pos: Vec<Option<PosTag>> = words_indexes.iter().map(|x| Some(matchable_words[**x as usize ] .pos_tag)).collect();
-    while pos.len() != 4 {
-        if pos.len() < 4 {
-            pos.push(None);
-        } else if pos.len() > 4 {
-            pos.pop();
-        }
-    }
-    pos.into_iter().collect_tuple().unwrap()
-}
-
 #[derive(Debug, Clone)]
 struct Matching {
     letter_pool: Letters,
@@ -666,98 +1622,53 @@ struct Matching {
 
 
 
-impl<'a> Matching {
+impl Matching {
 
-    fn best_permutation(&self, index: &Index, matchable_words: &[&Word]) -> (String, f32) {
-        let mut best_perm = vec![];
-        let mut best_score = -1.0;
+    /**
+     * The `nb_orderings` best orderings of the matched words, best first. With `grammatical_only`, the best grammatical
+     * orderings, which may be none.
+     */
+    fn best_permutations(
+        &self,
+        index: &Index,
+        matchable_words: &[WordId],
+        scorer: &dyn Scorer,
+        nb_orderings: usize,
+        grammatical_only: bool,
+    ) -> Orderings {
+        let words: Vec<WordId> = self.matched[..self.matched_size as usize]
+            .iter()
+            .map(|&word_index| matchable_words[word_index as usize])
+            .collect();
         if self.matched_size == 1 {
-            return (self.matched_to_string(&self.matched, index, matchable_words), f32::MAX);
+            return vec![(words, index.score_calibration(scorer.name()).single_word_score())];
         }
-        self.matched[..self.matched_size as usize]
-            .iter()
-            .permutations(self.matched_size as usize)
-            .for_each(|combination: Vec<&u16>| {
-                let mut score = Matching::score_combination(&combination, index, matchable_words);
-                let pos_n_gram: (Option<PosTag>, Option<PosTag>, Option<PosTag>, Option<PosTag>) = pos_tuple_from_words(&combination, &matchable_words);
-                if let Some(occs) = index.pos_n_grams.get(&pos_n_gram) {
-                    score *= occs;
-                }
-                if score > best_score {
-                    best_score = score;
-                    best_perm = combination;
-                }
-            });
-        let nb_small_words = self.matched[..self.matched_size as usize].iter()
-            .filter(|word_index| {
-                let word = matchable_words[**word_index as usize];
-                word.letters_sorted_range.end - word.letters_sorted_range.start <= 4
+        let orderings = if grammatical_only {
+            scorer.best_kept_orderings(&words, index, nb_orderings, &|ordered| {
+                agreement::is_grammatical(&best_readings(ordered, index), index)
             })
-            .count();
-        let mut best_perm_score = best_score / (self.matched.len().pow(2) as f32);
-        /* Penalize expression with lots of small words */
-        best_perm_score = best_perm_score / (1.0 + nb_small_words as f32).powf(1.5);
-        (self._matched_to_string(&best_perm, index, matchable_words), best_perm_score)
-    }
-
-    fn matched_to_string(&self, matched: &[u16], index: &Index, matchable_words: &[&Word]) -> String {
-        matched.iter()
-            .take(self.matched_size as usize)
-            .map(|word_index| {
-                let w: &Word = &matchable_words[*word_index as usize];
-                u8_to_str(
-                    &index.original_letters[w.letters_original_range.start as usize
-                        ..w.letters_original_range.end as usize],
-                )
-            })
-            .join(" ")
-    }
-
-    fn _matched_to_string(&self, matched: &[&u16], index: &Index, matchable_words: &[&Word]) -> String {
-        let m: Vec<u16> = matched.iter().map(|&&x| x).collect();
-        self.matched_to_string(&m, index, matchable_words)
-    }
-
-
-    fn score_combination(combination: &Vec<&u16>, index: &Index, matchable_words: &[&Word]) -> f32 {
-        let mut score = 0.0;
-        for window in combination.windows(2) {
-            let first = &matchable_words[*window[0] as usize];
-            let second = &matchable_words[*window[1] as usize];
-            let mut best_inner_score = 0.0;
-            for first_morph in &first.morph_tags {
-                for second_morph in &second.morph_tags {
-                    let first_pos_morph = PosMorph {
-                        morph: *first_morph,
-                        pos: first.pos_tag,
-                    };
-                    let second_pos_morph = PosMorph {
-                        morph: *second_morph,
-                        pos: second.pos_tag,
-                    };
-                    let key = (first_pos_morph, second_pos_morph);
-                    let value = index.tagging_stats.get(&key);
-                    if value.is_none() {
-                        continue;
-                    }
-                    let occ = value.unwrap();
-                    if *occ > best_inner_score {
-                        best_inner_score = *occ;
-                    };
-                }
-            }
-            score += best_inner_score;
-        }
-        let last = &matchable_words[**combination.last().unwrap() as usize];
-        /*  If last word is ADP, DET, PRON, VERB penalize current combination */
-        if last.pos_tag == PosTag::ADP || last.pos_tag == PosTag::DET || last.pos_tag == PosTag::PRON || last.pos_tag == PosTag::VERB
-        {
-            score /= 4.0;
-        }
-        score
+        } else {
+            scorer.best_orderings(&words, index, nb_orderings)
+        };
+        orderings
+            .into_iter()
+            .map(|(perm, score)| (perm.iter().map(|&i| words[i]).collect_vec(), score))
+            .collect()
     }
 }
 
+/** `items` in the `order` of their positions */
+fn reordered<T>(items: Vec<T>, order: Vec<usize>) -> Vec<T> {
+    let mut items = items.into_iter().map(Some).collect_vec();
+    order.into_iter().map(|i| items[i].take().unwrap()).collect()
+}
+
+fn words_to_string(words: &[WordId], index: &Index) -> String {
+    words.iter()
+        .map(|&word| u8_to_str(index.word_original_letters(word)))
+        .join(" ")
+}
+
 
 #[derive(Serialize)]
 struct ErrorMessage {
@@ -771,65 +1682,239 @@ struct QueryParams {
     #[serde(default)]
     search_type: SearchType,
     #[serde(default)]
+    letter_classes: String,
+    accent_bonus: Option<f32>,
+    max_accent_substitutions: Option<usize>,
+    #[serde(default)]
+    report_substitutions: bool,
+    #[serde(default)]
+    map_letters: bool,
+    #[serde(default)]
+    tokens: bool,
+    #[serde(default)]
     word_to_include: String,
+    #[serde(default)]
+    include_mode: MatchMode,
+    #[serde(default)]
+    word_to_exclude: String,
+    #[serde(default)]
+    exclude_mode: MatchMode,
+    orderings_per_result: Option<usize>,
+    #[serde(default)]
+    explain: bool,
+    #[serde(default)]
+    grammatical_only: bool,
+    scorer: Option<String>,
+    #[serde(default)]
+    semantic: SemanticPreference,
+    reuse_penalty: Option<f32>,
+    #[serde(default)]
+    forbid_reuse: bool,
+    commonness_weight: Option<f32>,
+    diversity: Option<f32>,
+    max_word_repeats: Option<usize>,
+    #[serde(default)]
+    sort_by: SortBy,
+    #[serde(default)]
+    group_by: GroupBy,
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+impl QueryParams {
+    /** `default_scorer` is used when the query does not name one */
+    fn search_options(&self, default_scorer: &str) -> SearchOptions {
+        let default = SearchOptions::default();
+        SearchOptions {
+            search_type: self.search_type,
+            letter_classes: self.letter_classes.clone(),
+            accent_bonus: self.accent_bonus.unwrap_or(match self.search_type {
+                SearchType::PREFER_EXACT => DEFAULT_ACCENT_BONUS,
+                _ => default.accent_bonus,
+            }),
+            max_accent_substitutions: self.max_accent_substitutions,
+            report_substitutions: self.report_substitutions,
+            map_letters: self.map_letters,
+            tokens: self.tokens,
+            word_to_include: self.word_to_include.clone(),
+            include_mode: self.include_mode,
+            word_to_exclude: self.word_to_exclude.clone(),
+            exclude_mode: self.exclude_mode,
+            orderings_per_result: self.orderings_per_result.unwrap_or(default.orderings_per_result),
+            explain: self.explain,
+            grammatical_only: self.grammatical_only,
+            semantic: self.semantic,
+            reuse_penalty: self.reuse_penalty.unwrap_or(default.reuse_penalty),
+            forbid_reuse: self.forbid_reuse,
+            commonness_weight: self.commonness_weight.unwrap_or(default.commonness_weight),
+            diversity: self.diversity.unwrap_or(default.diversity),
+            max_word_repeats: self.max_word_repeats.unwrap_or(default.max_word_repeats),
+            sort_by: self.sort_by,
+            group_by: self.group_by,
+            truncation_seed: None,
+            offset: self.offset,
+            limit: self.limit,
+            scorer: self.scorer.clone().unwrap_or_else(|| String::from(default_scorer)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LemmaParams {
+    lemma: String,
+    /** Only the forms that can be written with these letters are listed, all of them when empty */
+    #[serde(default)]
+    input: String,
+    #[serde(default)]
+    search_type: SearchType,
+    #[serde(default)]
+    letter_classes: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct LemmaForms {
+    lemma: String,
+    forms: Vec<String>,
+}
+
+/** The result as json, or the error message with a 400 status */
+fn reply<T: serde::Serialize>(result: Result<T, String>) -> warp::reply::WithStatus<warp::reply::Json> {
+    match result {
+        Ok(res) => warp::reply::with_status(warp::reply::json(&res), StatusCode::OK),
+        Err(msg) => {
+            let json = warp::reply::json(&ErrorMessage {
+                code: StatusCode::BAD_REQUEST.as_u16(),
+                message: msg,
+            });
+
+            warp::reply::with_status(json, StatusCode::BAD_REQUEST)
+        }
+    }
 }
 
 // use std::mem;
 #[tokio::main]
 async fn main() {
-    // println!("Size of word: {}", mem::size_of::<Word>());
+    // println!("Size of word defs: {}", mem::size_of::<WordDefs>());
     // println!("Size of matching: {}", mem::size_of::<Matching>());
     // println!("Size of Letters: {}", mem::size_of::<Letters>());
-    // bench_estimate();
-
+    if std::env::args().any(|arg| arg == "bench") {
+        bench_estimate();
+        return;
+    }
+    if std::env::args().any(|arg| arg == "build-index") {
+        Index::build_file().expect("Cannot write index file");
+        return;
+    }
 
-    let index: Index = Index::new();
-    let route = warp::path!("engine"/"query")
+    let default_scorer = std::env::var("ANAGRAMDR_SCORER").unwrap_or_else(|_| String::from(DEFAULT_SCORER));
+    if scorer_by_name(&default_scorer).is_none() {
+        panic!("Unknown scorer {} in ANAGRAMDR_SCORER", default_scorer);
+    }
+    println!("Default scorer: {}", default_scorer);
+    let before = Instant::now();
+    let index = Index::load();
+    println!("Index loaded in {:.2?}", before.elapsed());
+    let index = Arc::new(RwLock::new(Arc::new(with_overrides(index))));
+    tokio::spawn(reload_overrides_on_sighup(index.clone()));
+    let lemma_index = index.clone();
+    let query_route = warp::path!("engine"/"query")
     .and(warp::query::<QueryParams>())
     .map(move |q: QueryParams| {
             let query_input: String = decode(&q.input).expect("UTF-8").into_owned();
+            let index = index.read().unwrap().clone();
             // let before = Instant::now();
-            let results = index.find_anagrams_reverse(query_input, q.search_type, q.word_to_include);
+            let results = index.find_anagrams_reverse(query_input, &q.search_options(&default_scorer));
             // println!("Elapsed time: {:.2?}", before.elapsed());
-            match results {
-                Ok(res) => return warp::reply::with_status(warp::reply::json(&res), StatusCode::OK),
-                Err(msg) => {
-                    let json = warp::reply::json(&ErrorMessage {
-                        code: StatusCode::BAD_REQUEST.as_u16(),
-                        message: msg.into(),
-                    });
-                
-                    return warp::reply::with_status(json, StatusCode::BAD_REQUEST);
-                }
-            }
-            
+            reply(results)
         });
+    let lemma_route = warp::path!("engine"/"lemma")
+    .and(warp::query::<LemmaParams>())
+    .map(move |q: LemmaParams| {
+            let input: String = decode(&q.input).expect("UTF-8").into_owned();
+            let index = lemma_index.read().unwrap().clone();
+            let classes = LetterClasses::new(q.search_type, &q.letter_classes);
+            reply(classes.and_then(|classes| index.lemma_forms(&q.lemma, input, &classes)))
+        });
+    let route = query_route.or(lemma_route);
     warp::serve(route).run(([127, 0, 0, 1], 3030)).await;
 }
 
+/** The index with the overrides of `OVERRIDES_PATH` applied */
+fn with_overrides(mut index: Index) -> Index {
+    overrides::apply(&mut index, &overrides::load(OVERRIDES_PATH));
+    index
+}
+
+/**
+ * Apply the overrides again each time the process gets SIGHUP, queries being served meanwhile. The index without
+ * overrides is not kept aside, which would double its memory: it is loaded again, see `Index::load`.
+ */
+async fn reload_overrides_on_sighup(index: Arc<RwLock<Arc<Index>>>) {
+    let Ok(mut hangups) = signal(SignalKind::hangup()) else {
+        println!("Cannot listen to SIGHUP, overrides will not be reloaded");
+        return;
+    };
+    while hangups.recv().await.is_some() {
+        println!("Reloading {}", OVERRIDES_PATH);
+        match tokio::task::spawn_blocking(|| with_overrides(Index::load())).await {
+            Ok(reloaded) => *index.write().unwrap() = Arc::new(reloaded),
+            Err(e) => println!("Overrides not reloaded: {}", e),
+        }
+    }
+}
 
 fn bench_estimate() {
-    let index: Index = Index::new();
+    let index: Index = Index::load();
     let queries = [
         String::from("montceau les mines"),
         String::from("alain chabat le meilleur"),
         String::from("le marquis de sade"),
         String::from("j'ai la belle vie madame"),
+        String::from("emmanuel macron"),
+        String::from("les miserables de victor hugo"),
+        String::from("charles de gaulle et la resistance"),
     ];
 
     // println!("{}", index);
     for query in queries {
+        let sorted_input = index.process_input(query.clone());
+        let matchable_words = index.filter_matchable_words(&sorted_input, &ROOT_CLASSES);
+        let word_sizes = matchable_words.iter().map(|&w| index.word_defs.nb_letters(w));
+        let cost = QueryCost::new(sorted_input.len(), word_sizes);
+        let plan = cost.plan(LATENCY_BUDGET_MS);
+        println!("{} matchable words, {:?}", matchable_words.len(), plan);
+        let nb_searched = match plan {
+            QueryPlan::Truncated { nb_words, .. } => nb_words,
+            _ => matchable_words.len(),
+        };
         let before = Instant::now();
         let copy: String = query.clone();
-        let _words = index.find_anagrams_reverse(query, SearchType::ROOT, String::from(""));
-        println!("{}: {:.2?}", copy, before.elapsed());
+        let result = index.find_anagrams_reverse(query, &SearchOptions::default());
+        let elapsed = before.elapsed();
+        let estimated_ms = cost.estimate_ms(nb_searched);
+        match result {
+            Ok(_) => println!("{}: {:.2?} (estimated {:.2}ms, budget {}ms)", copy, elapsed, estimated_ms, LATENCY_BUDGET_MS),
+            Err(e) => println!("{}: {} ({:.2?}, estimated {:.2}ms)", copy, e, elapsed, estimated_ms),
+        }
+    }
+
+    /* Orderings above `EXHAUSTIVE_ORDERING_SIZE` words, found by dynamic programming */
+    let sorted_input = index.process_input(String::from("le marquis de sade"));
+    let matchable_words = index.filter_matchable_words(&sorted_input, &ROOT_CLASSES);
+    for nb_words in EXHAUSTIVE_ORDERING_SIZE + 1..=MAX_EXPR_SIZE {
+        let words = &matchable_words[..nb_words];
+        let before = Instant::now();
+        scorer_by_name(DEFAULT_SCORER).unwrap().best_orderings(words, &index, MAX_ORDERINGS_PER_RESULT);
+        println!("Orderings of {} words: {:.2?}", words.len(), before.elapsed());
     }
-            
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use letter_classes::EXACT_CLASSES;
 
     fn str_to_sorted_encoded(input: &str) -> Vec<u8> {
         let mut encoded = str_to_u8(input);
@@ -837,6 +1922,46 @@ mod tests {
         encoded
     }
 
+    /** A tiny index, with made up stats, to test without the data files */
+    pub(crate) fn test_index() -> Index {
+        let masc_sing = Morph {
+            gender: Some(Gender::Masc),
+            number: Some(Number::Sing),
+            ..Morph::default()
+        };
+        let third_sing = Morph {
+            number: Some(Number::Sing),
+            person: Some(Person::Three),
+            ..Morph::default()
+        };
+        let mut index = Index::default();
+        index.push_word("il", PosTag::PRON, vec![masc_sing], 8000.0);
+        index.push_word("le", PosTag::DET, vec![masc_sing], 15000.0);
+        index.push_word("un", PosTag::DET, vec![masc_sing], 10000.0);
+        index.push_word("lie", PosTag::VERB, vec![third_sing], 10.0);
+        index.push_word("lit", PosTag::NOUN, vec![masc_sing], 60.0);
+        index.push_word("nul", PosTag::ADJ, vec![masc_sing], 20.0);
+        index.push_word("tel", PosTag::ADJ, vec![masc_sing], 200.0);
+        let det = PosMorph { pos: PosTag::DET, morph: masc_sing };
+        let noun = PosMorph { pos: PosTag::NOUN, morph: masc_sing };
+        let adj = PosMorph { pos: PosTag::ADJ, morph: masc_sing };
+        let pron = PosMorph { pos: PosTag::PRON, morph: masc_sing };
+        let verb = PosMorph { pos: PosTag::VERB, morph: third_sing };
+        index.tagging_stats.insert((pron, verb), 80.0);
+        index.tagging_stats.insert((verb, det), 30.0);
+        index.tagging_stats.insert((det, noun), 100.0);
+        index.tagging_stats.insert((noun, adj), 50.0);
+        index.tagging_stats.insert((adj, noun), 10.0);
+        index.pos_n_grams.insert(&[PosTag::DET, PosTag::NOUN, PosTag::ADJ], 20.0);
+        index.finalize();
+        index
+    }
+
+    /** Id of a word of the index, given as written */
+    pub(crate) fn word_id(index: &Index, w: &str) -> WordId {
+        (0..index.word_defs.len() as WordId).find(|&m| u8_to_str(index.word_original_letters(m)) == w).unwrap()
+    }
+
     #[test]
     fn char_encoding_decoding() {
         let encoded = char_to_u8('e');
@@ -863,12 +1988,13 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn encoded_comparison() {
         assert_eq!(
             Index::check_contains_all_letters(
                 &str_to_sorted_encoded("efforça"),
                 &str_to_sorted_encoded("efforça"),
-                SearchType::EXACT
+                &EXACT_CLASSES
             ),
             true
         );
@@ -876,7 +2002,7 @@ mod tests {
             Index::check_contains_all_letters(
                 &str_to_sorted_encoded("abcdefg"),
                 &str_to_sorted_encoded("efg"),
-                SearchType::EXACT
+                &EXACT_CLASSES
             ),
             true
         );
@@ -884,7 +2010,7 @@ mod tests {
             Index::check_contains_all_letters(
                 &str_to_sorted_encoded("abcdefg"),
                 &str_to_sorted_encoded("abc"),
-                SearchType::EXACT
+                &EXACT_CLASSES
             ),
             true
         );
@@ -892,7 +2018,7 @@ mod tests {
             Index::check_contains_all_letters(
                 &str_to_sorted_encoded("abcdefg"),
                 &str_to_sorted_encoded("abh"),
-                SearchType::EXACT
+                &EXACT_CLASSES
             ),
             false
         );
@@ -900,7 +2026,7 @@ mod tests {
             Index::check_contains_all_letters(
                 &str_to_sorted_encoded("efforça"),
                 &str_to_sorted_encoded("efforça"),
-                SearchType::ROOT
+                &ROOT_CLASSES
             ),
             true
         );
@@ -908,7 +2034,7 @@ mod tests {
             Index::check_contains_all_letters(
                 &str_to_sorted_encoded("efforca"),
                 &str_to_sorted_encoded("efforça"),
-                SearchType::ROOT
+                &ROOT_CLASSES
             ),
             true
         );
@@ -916,7 +2042,7 @@ mod tests {
             Index::check_contains_all_letters(
                 &str_to_sorted_encoded("efforca"),
                 &str_to_sorted_encoded("efforça"),
-                SearchType::EXACT
+                &EXACT_CLASSES
             ),
             false
         );
@@ -928,7 +2054,7 @@ mod tests {
             Index::new_vec_removed_letters(
                 &str_to_sorted_encoded("efforça"),
                 &str_to_sorted_encoded("efforç"),
-                SearchType::EXACT
+                &EXACT_CLASSES
             ),
             [0]
         );
@@ -937,13 +2063,14 @@ mod tests {
             Index::new_vec_removed_letters(
                 &str_to_sorted_encoded("efforça"),
                 &str_to_sorted_encoded("effora"),
-                SearchType::EXACT
+                &EXACT_CLASSES
             ),
             [missing]
         );
     }
 
     #[test]
+    #[allow(clippy::unnecessary_cast)]
     fn bloom_filter_test() {
         let bloom1 = encoded_letters_to_bloom_u32(&str_to_sorted_encoded("abcdef"));
         assert_eq!(bloom1, (2 as u32).pow(6) - 1);
@@ -958,4 +2085,348 @@ mod tests {
         let bloom2 = encoded_letters_to_bloom_u32(&str_to_sorted_encoded("deelqsu"));
         assert!((bloom1 & bloom2) != bloom2);
     }
+
+    #[test]
+    fn morph_from_serde_map() {
+        let json: Value = serde_json::from_str(
+            r#"{"Number": "Sing", "Person": "3", "VerbForm": "Fin", "Tense": "Pres", "Mood": "Ind", "Polarity": "Neg", "Gender": "Fem,Masc"}"#,
+        )
+        .unwrap();
+        let morph = Morph::from_serde_map(json.as_object().unwrap());
+        assert_eq!(
+            morph,
+            Morph {
+                number: Some(Number::Sing),
+                person: Some(Person::Three),
+                verb_form: Some(VerbForm::Fin),
+                tense: Some(Tense::Pres),
+                mood: Some(Mood::Ind),
+                ..Morph::default()
+            }
+        );
+        assert_eq!(morph.tagging_stats_key(), Morph { number: Some(Number::Sing), person: Some(Person::Three), ..Morph::default() });
+    }
+
+    #[test]
+    fn malformed_word_bigrams_are_skipped() {
+        let mut index = test_index();
+        let lines = [
+            r#"{"2-gram": ["le", "lit"], "occ": 16}"#,
+            r#"{"2-gram": ["le", "lit"#,
+            r#"{"2-gram": ["le"], "occ": 3}"#,
+            r#"{"2-gram": ["lit", 2], "occ": 3}"#,
+            r#"{"2-gram": ["lit", "nul"]}"#,
+            r#"{"2-gram": ["un", "inconnu"], "occ": 3}"#,
+            r#"{"2-gram": ["lit", "nul"], "occ": 9}"#,
+        ];
+        index.load_word_bigrams(lines.into_iter().map(|l| Ok(String::from(l))));
+        let [le, lit, nul] = ["le", "lit", "nul"].map(|w| word_id(&index, w));
+        assert_eq!(index.word_bigrams.len(), 2);
+        assert_eq!(index.word_bigram_score(le, lit), Some(4.0));
+        assert_eq!(index.word_bigram_score(lit, nul), Some(3.0));
+    }
+
+    #[test]
+    fn verb_forms_weight_pair_scores() {
+        let mut index = test_index();
+        let infinitive = Morph { verb_form: Some(VerbForm::Inf), ..Morph::default() };
+        let finite = Morph { verb_form: Some(VerbForm::Fin), ..Morph::default() };
+        index.push_word("de", PosTag::ADP, vec![Morph::default()], 0.0);
+        index.push_word("lier", PosTag::VERB, vec![infinitive], 0.0);
+        index.push_word("lie", PosTag::VERB, vec![finite], 0.0);
+        let adp = PosMorph { pos: PosTag::ADP, morph: Morph::default() };
+        let verb = PosMorph { pos: PosTag::VERB, morph: Morph::default() };
+        index.tagging_stats.insert((adp, verb), 10.0);
+        index.finalize();
+        let nb_words = index.word_defs.len() as WordId;
+        let (de, lier, lie) = (nb_words - 3, nb_words - 2, nb_words - 1);
+        assert_eq!(index.reading_pair_score(de, lier), 15.0);
+        assert_eq!(index.reading_pair_score(de, lie), 5.0);
+    }
+
+    #[test]
+    fn homographs_have_several_readings() {
+        let mut index = test_index();
+        let noun = Morph { gender: Some(Gender::Masc), number: Some(Number::Sing), ..Morph::default() };
+        let verb = Morph { number: Some(Number::Sing), person: Some(Person::Three), ..Morph::default() };
+        index.push_homographs(
+            "est",
+            vec![(PosTag::NOUN, vec![noun], None), (PosTag::VERB, vec![verb], None), (PosTag::NOUN, vec![noun], None)],
+            0.0,
+        );
+        index.finalize();
+        let est = word_id(&index, "est");
+        assert_eq!(index.word_readings(est).len(), 2);
+        assert_eq!(index.word_pos(est), PosTag::NOUN);
+        /* Each pair of words is scored with its best readings */
+        let [le, lit, il, lie] = ["le", "lit", "il", "lie"].map(|w| word_id(&index, w));
+        assert_eq!(index.reading_pair_score(le, est), index.reading_pair_score(le, lit));
+        assert_eq!(index.reading_pair_score(il, est), index.reading_pair_score(il, lie));
+        /* As after loading, the reading sets are interned again by the overrides */
+        index.reading_set_ids.clear();
+        let nb_reading_sets = index.reading_sets.len();
+        index.set_readings(lie, vec![(PosTag::NOUN, vec![noun], None), (PosTag::VERB, vec![verb], None)]);
+        assert_eq!(index.reading_sets.len(), nb_reading_sets);
+        assert_eq!(index.word_defs.reading_sets[lie as usize], index.word_defs.reading_sets[est as usize]);
+    }
+
+    #[test]
+    fn common_words_are_favoured() {
+        let index = test_index();
+        let common = [word_id(&index, "le"), word_id(&index, "lit"), word_id(&index, "tel")];
+        let rare = [word_id(&index, "le"), word_id(&index, "lie"), word_id(&index, "nul")];
+        assert!(index.commonness_factor(&common, DEFAULT_COMMONNESS_WEIGHT) > index.commonness_factor(&rare, DEFAULT_COMMONNESS_WEIGHT));
+        assert_eq!(index.commonness_factor(&common, 0.0), 1.0);
+        assert_eq!(index.commonness_factor(&[word_id(&index, "le")], DEFAULT_COMMONNESS_WEIGHT), 1.0);
+        /* Geometric mean of 1 + frequency */
+        let factor = index.commonness_factor(&[word_id(&index, "lie"), word_id(&index, "nul")], 1.0);
+        assert!((factor - (11.0f32 * 21.0).sqrt()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn include_and_exclude_lemmas() {
+        let mut index = test_index();
+        index.push_word("lu", PosTag::VERB, vec![], 0.0);
+        index.finalize();
+        let (lit, lu) = (word_id(&index, "lit"), word_id(&index, "lu"));
+        index.add_lemma_form("lire", lit);
+        index.add_lemma_form("lire", lu);
+        let all_words = (0..index.word_defs.len() as WordId).collect_vec();
+        assert_eq!(index.words_matching("Lire", MatchMode::Lemma, &ROOT_CLASSES, &all_words), vec![lit, lu]);
+        assert_eq!(index.words_matching("Lire", MatchMode::Lemma, &ROOT_CLASSES, &[lu]), vec![lu]);
+        assert_eq!(index.words_matching("lît", MatchMode::Word, &ROOT_CLASSES, &all_words), vec![lit]);
+        assert!(index.words_matching("lît", MatchMode::Word, &EXACT_CLASSES, &all_words).is_empty());
+
+        let texts = |options: &SearchOptions| -> Vec<String> {
+            let result = index.find_anagrams_reverse(String::from("lit nul"), options).unwrap();
+            result.anagrams.into_iter().map(|a| a.0).collect()
+        };
+        let with_lire = SearchOptions { word_to_include: String::from("lire"), include_mode: MatchMode::Lemma, ..SearchOptions::default() };
+        let included = texts(&with_lire);
+        assert!(!included.is_empty() && included.iter().all(|a| a.split(' ').any(|w| w == "lit" || w == "lu")));
+        let without_lire = SearchOptions { word_to_exclude: String::from("lire"), exclude_mode: MatchMode::Lemma, ..SearchOptions::default() };
+        assert!(!texts(&SearchOptions::default()).is_empty());
+        assert!(texts(&without_lire).is_empty());
+        let with_lire_form = SearchOptions { word_to_include: String::from("lire"), ..SearchOptions::default() };
+        assert!(index.find_anagrams_reverse(String::from("lit nul"), &with_lire_form).is_err());
+
+        let forms = index.lemma_forms("lire", String::from("il lu"), &ROOT_CLASSES).unwrap();
+        assert_eq!(forms, LemmaForms { lemma: String::from("lire"), forms: vec![String::from("lu")] });
+        assert!(index.lemma_forms("lier", String::new(), &ROOT_CLASSES).is_err());
+    }
+
+    #[test]
+    fn grammatical_orderings_are_searched_past_the_best_ones() {
+        let mut index = test_index();
+        let fem_sing = Morph { gender: Some(Gender::Fem), number: Some(Number::Sing), ..Morph::default() };
+        let third_plur = Morph { number: Some(Number::Plur), person: Some(Person::Three), ..Morph::default() };
+        index.push_word("lient", PosTag::VERB, vec![third_plur], 0.0);
+        index.push_word("belle", PosTag::ADJ, vec![fem_sing], 0.0);
+        let noun = PosMorph { pos: PosTag::NOUN, morph: Morph { gender: Some(Gender::Masc), ..fem_sing } };
+        let verb = PosMorph { pos: PosTag::VERB, morph: third_plur };
+        let adj = PosMorph { pos: PosTag::ADJ, morph: fem_sing };
+        for (pair, stat) in [((noun, verb), 80.0), ((verb, adj), 60.0), ((noun, adj), 50.0), ((adj, noun), 40.0), ((adj, verb), 1.0), ((verb, noun), 1.0)] {
+            index.tagging_stats.insert(pair, stat);
+        }
+        index.finalize();
+        let words = [word_id(&index, "lit"), word_id(&index, "lient"), word_id(&index, "belle")];
+        let scorer = scorer_by_name(DEFAULT_SCORER).unwrap();
+        /* The only grammatical ordering is the worst one */
+        let ordered = scorer.best_orderings(&words, &index, 6)
+            .into_iter()
+            .map(|(perm, _)| perm.iter().map(|&i| words[i]).collect_vec())
+            .collect_vec();
+        let grammatical = |ordered: &[WordId]| agreement::is_grammatical(&best_readings(ordered, &index), &index);
+        assert_eq!(ordered.iter().filter(|o| grammatical(o)).collect_vec(), vec![&ordered[5]]);
+        assert_eq!(words_to_string(&ordered[5], &index), "belle lient lit");
+
+        let options = SearchOptions { grammatical_only: true, ..SearchOptions::default() };
+        let result = index.find_anagrams_reverse(String::from("lit lient belle"), &options).unwrap();
+        assert!(result.anagrams.iter().any(|a| a.0 == "belle lient lit"));
+        let result = index.find_anagrams_reverse(String::from("lit lient belle"), &SearchOptions::default()).unwrap();
+        assert!(!result.anagrams.iter().any(|a| a.0 == "belle lient lit"));
+    }
+
+    #[test]
+    fn exact_accents_are_favoured() {
+        let mut index = test_index();
+        index.push_word("lé", PosTag::NOUN, vec![], 0.0);
+        index.finalize();
+        let input = index.process_input(String::from("le nul"));
+        assert_eq!(index.accent_substitutions(&[word_id(&index, "le"), word_id(&index, "nul")], &input), 0);
+        assert_eq!(index.accent_substitutions(&[word_id(&index, "lé"), word_id(&index, "nul")], &input), 1);
+
+        let texts = |options: &SearchOptions| -> Vec<String> {
+            let result = index.find_anagrams_reverse(String::from("le nul"), options).unwrap();
+            result.anagrams.into_iter().map(|a| a.0).collect()
+        };
+        let all = texts(&SearchOptions::default());
+        assert!(all.iter().any(|a| a.contains("lé")));
+        let capped = texts(&SearchOptions { max_accent_substitutions: Some(0), ..SearchOptions::default() });
+        assert!(!capped.is_empty() && capped.iter().all(|a| !a.contains("lé")));
+        let options = SearchOptions { report_substitutions: true, ..SearchOptions::default() };
+        let result = index.find_anagrams_reverse(String::from("le nul"), &options).unwrap();
+        for ((text, _, _), detail) in result.anagrams.iter().zip(&result.details) {
+            assert_eq!(detail.substitutions.len(), text.contains("lé") as usize);
+        }
+    }
+
+    #[test]
+    fn letter_classes_restrict_the_accents() {
+        let mut index = test_index();
+        index.push_word("lé", PosTag::NOUN, vec![], 0.0);
+        index.push_word("lè", PosTag::NOUN, vec![], 0.0);
+        index.finalize();
+        let texts = |options: &SearchOptions| -> Vec<String> {
+            let result = index.find_anagrams_reverse(String::from("le nul"), options).unwrap();
+            result.anagrams.into_iter().map(|a| a.0).collect()
+        };
+        assert!(texts(&SearchOptions::default()).iter().any(|a| a.contains("lè")));
+        /* "e" and "é" only stand for each other, their keys are not sorted as the letters any more */
+        let options = SearchOptions { letter_classes: String::from("eé"), accent_bonus: 2.0, ..SearchOptions::default() };
+        let with_classes = texts(&options);
+        assert!(with_classes.iter().any(|a| a.contains("lé")) && with_classes.iter().all(|a| !a.contains("lè")));
+        let score = |options: &SearchOptions| {
+            let result = index.find_anagrams_reverse(String::from("le nul"), options).unwrap();
+            result.anagrams.into_iter().find(|a| a.0.contains("lé")).unwrap().1
+        };
+        let without_bonus = SearchOptions { accent_bonus: 1.0, ..options.clone() };
+        assert_eq!(score(&options), score(&without_bonus) / 2.0);
+    }
+
+    #[test]
+    fn explained_score_is_the_returned_score() {
+        let mut index = test_index();
+        index.push_word("lé", PosTag::NOUN, vec![], 0.0);
+        index.finalize();
+        let options = SearchOptions { explain: true, accent_bonus: 2.0, commonness_weight: 1.0, ..SearchOptions::default() };
+        let result = index.find_anagrams_reverse(String::from("le lit nul"), &options).unwrap();
+        assert!(!result.anagrams.is_empty());
+        let scorer = scorer_by_name(DEFAULT_SCORER).unwrap();
+        let mut any_query_factor = false;
+        for ((text, score, _), detail) in result.anagrams.iter().zip(&result.details) {
+            assert_eq!(detail.explanation.as_ref().unwrap().score, *score, "{text}");
+            let words = text.split(' ').map(|w| word_id(&index, w)).collect_vec();
+            any_query_factor |= scorer.score(&words, &index) != *score;
+        }
+        assert!(any_query_factor);
+    }
+
+    #[test]
+    fn tokens_carry_the_chosen_readings() {
+        let index = test_index();
+        let options = SearchOptions { tokens: true, ..SearchOptions::default() };
+        let result = index.find_anagrams_reverse(String::from("le lit"), &options).unwrap();
+        let (text, _, _) = &result.anagrams[0];
+        let tokens = &result.details[0].tokens;
+        assert_eq!(tokens.iter().map(|t| t.surface.as_str()).join(" "), *text);
+        let le = tokens.iter().find(|t| t.surface == "le").unwrap();
+        assert_eq!(le.pos, PosTag::DET);
+        assert_eq!(u8_to_str(index.word_original_letters(le.word_id)), "le");
+        let features: BTreeMap<&str, String> = [("Gender", "Masc"), ("Number", "Sing")].into_iter().map(|(k, v)| (k, String::from(v))).collect();
+        assert_eq!(le.morph, features);
+        assert!(le.other_morphs.is_empty());
+
+        /* The determiner is followed by a masculine singular noun in the stats */
+        let mut index = test_index();
+        let fem_plur = Morph { gender: Some(Gender::Fem), number: Some(Number::Plur), ..Morph::default() };
+        let masc_sing = Morph { gender: Some(Gender::Masc), number: Some(Number::Sing), ..Morph::default() };
+        index.push_word("mas", PosTag::NOUN, vec![fem_plur, masc_sing], 0.0);
+        index.finalize();
+        let tokens = index.tokens(&[word_id(&index, "le"), word_id(&index, "mas")]);
+        assert_eq!(tokens[1].morph, masc_sing.features());
+        assert_eq!(tokens[1].other_morphs, vec![fem_plur.features()]);
+        let third = Morph { person: Some(Person::Three), ..Morph::default() };
+        assert_eq!(third.features().get("Person").map(String::as_str), Some("3"));
+    }
+
+    #[test]
+    fn seeded_truncations_are_reproducible() {
+        let mut index = test_index();
+        for letters in "abcdefgh".chars().permutations(5).take(400) {
+            index.push_word(&letters.into_iter().collect::<String>(), PosTag::NOUN, vec![], 0.0);
+        }
+        index.finalize();
+        let input = index.process_input(String::from("abcdefghabcdefghab"));
+        let words = (0..index.word_defs.len() as WordId).filter(|&w| index.word_defs.nb_letters(w) == 5).collect_vec();
+        let truncated = |seed| index.get_matchable_words(&input, words.clone(), &[], &[], false, seed).unwrap();
+        let (first, was_truncated, _) = truncated(CALIBRATION_SEED);
+        assert!(was_truncated && first.len() < words.len());
+        assert_eq!(truncated(CALIBRATION_SEED).0, first);
+        assert_ne!(truncated(CALIBRATION_SEED + 1).0, first);
+    }
+
+    #[test]
+    fn truncations_keep_the_words_keeping_the_accents_first() {
+        let mut index = test_index();
+        for letters in "ébcdefgh".chars().permutations(5).take(400) {
+            index.push_word(&letters.into_iter().collect::<String>(), PosTag::NOUN, vec![], 0.0);
+        }
+        for letters in "abcdefgh".chars().permutations(5).take(50) {
+            index.push_word(&letters.into_iter().collect::<String>(), PosTag::NOUN, vec![], 0.0);
+        }
+        index.finalize();
+        let input = index.process_input(String::from("abcdefghabcdefghab"));
+        let words = index.filter_matchable_words(&input, &ROOT_CLASSES);
+        let exact = words.iter().copied().filter(|&w| index.accent_substitutions(&[w], &input) == 0).collect_vec();
+        let kept = |prefer_exact| {
+            let (kept, was_truncated, _) = index.get_matchable_words(&input, words.clone(), &[], &[], prefer_exact, 0).unwrap();
+            assert!(was_truncated && kept.len() > exact.len());
+            exact.iter().filter(|w| kept.contains(w)).count()
+        };
+        assert_eq!(kept(true), exact.len());
+        assert!(kept(false) < exact.len());
+    }
+
+    #[test]
+    fn pages_of_truncated_queries_are_taken_from_the_same_results() {
+        let mut index = test_index();
+        /* Words with a "z" cannot be combined, only those with the letters "abcd" complete the two longest words */
+        for letters in "abcdefgh".chars().permutations(4).take(300) {
+            index.push_word(&format!("z{}", letters.into_iter().collect::<String>()), PosTag::NOUN, vec![], 0.0);
+        }
+        index.push_word("efghij", PosTag::NOUN, vec![], 0.0);
+        index.push_word("klmnopq", PosTag::NOUN, vec![], 0.0);
+        index.finalize();
+        let query = || String::from("zabcdefgh ijklmnopq");
+        let options = SearchOptions { sort_by: SortBy::Alphabetical, ..SearchOptions::default() };
+        let all = index.find_anagrams_reverse(query(), &options).unwrap();
+        assert!(all.was_truncated && all.total > 2);
+        let pages = (0..all.total).step_by(2).flat_map(|offset| {
+            let page = SearchOptions { offset, limit: Some(2), ..options.clone() };
+            index.find_anagrams_reverse(query(), &page).unwrap().anagrams
+        });
+        assert!(pages.eq(all.anagrams));
+    }
+
+    #[test]
+    fn single_words_are_scored_as_the_best_calibrated_anagrams() {
+        let mut index = test_index();
+        let calibration = ScoreCalibration::new(&[0.5, 1.0, 2.0, 4.0, 8.0]);
+        index.score_calibrations.insert(String::from(DEFAULT_SCORER), calibration.clone());
+        let result = index.find_anagrams_reverse(String::from("til"), &SearchOptions::default()).unwrap();
+        let (_, score, normalized) = result.anagrams.iter().find(|a| a.0 == "lit").unwrap();
+        assert!((score - 8.0).abs() < 1e-3);
+        assert_eq!(*normalized, 1.0);
+        let result = index.find_anagrams_reverse(String::from("le lit nul"), &SearchOptions::default()).unwrap();
+        assert!(result.anagrams.iter().all(|&(_, score, normalized)| {
+            (0.0..=1.0).contains(&normalized) && normalized == calibration.normalize(score)
+        }));
+    }
+
+    #[test]
+    fn sorted_results_are_paginated() {
+        let index = test_index();
+        let options = SearchOptions { sort_by: SortBy::Alphabetical, group_by: GroupBy::MainWord, ..SearchOptions::default() };
+        let all = index.find_anagrams_reverse(String::from("le lit nul"), &options).unwrap();
+        assert_eq!(all.total, all.anagrams.len());
+        assert_eq!(all.groups.len(), all.anagrams.len());
+        assert!(all.total >= 2);
+        let page = SearchOptions { offset: 1, limit: Some(1), ..options };
+        let page = index.find_anagrams_reverse(String::from("le lit nul"), &page).unwrap();
+        assert_eq!(page.total, all.total);
+        let texts = |result: &AnagramResult| result.anagrams.iter().map(|a| a.0.clone()).collect_vec();
+        assert_eq!(texts(&page), texts(&all)[1..2]);
+        assert_eq!(page.groups, all.groups[1..2]);
+    }
 }
@@ -0,0 +1,164 @@
+/**
+ * Fixes of the words of the index at runtime, for the mistakes of the datasets that are not worth running the whole
+ * nlp pipeline again. The optional file (`OVERRIDES_PATH`) has one override per line:
+ *
+ * {"action": "fix", "word": "ai", "pos": "AUX", "morph": [{"Number": "Sing", "Person": "1"}]}
+ * {"action": "add", "word": "kiffe", "lemma": "kiffer", "pos": "VERB", "morph": [], "freq": 5.0}
+ * {"action": "remove", "word": "ania"}
+ *
+ * The readings are given as in the words file, either as "pos" and "morph" or as a list of "readings", and so are the
+ * lemmas of the added words. The overrides
+ * are applied in order on top of the index as loaded at startup, and again when the server gets SIGHUP, on top of the
+ * index loaded again. Added words get the next ids, after the words of the index whatever their length (see `WordDefs`).
+ */
+use crate::{Index, WordId, WordReadings, ALLOWED_CHARS};
+use serde_json::Value;
+use std::fs;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Override {
+    /** Replace the readings of a word of the index */
+    Fix { word: String, readings: WordReadings },
+    /** Add a word, or bring back a removed one */
+    Add { word: String, readings: WordReadings, frequency: f32, lemmas: Vec<String> },
+    Remove { word: String },
+}
+
+fn parse(line: &str) -> Result<Override, String> {
+    let value: Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let word = value["word"].as_str().ok_or("missing word")?.to_lowercase();
+    if !word.chars().all(|x| ALLOWED_CHARS.contains(x)) {
+        return Err(format!("{} not in character set", word));
+    }
+    let readings = || -> Result<WordReadings, String> {
+        match value["readings"].as_array() {
+            Some(readings) => readings.iter().map(Index::build_reading).collect(),
+            None => Ok(vec![Index::build_reading(&value)?]),
+        }
+    };
+    match value["action"].as_str() {
+        Some("fix") => Ok(Override::Fix { word, readings: readings()? }),
+        Some("add") => Ok(Override::Add {
+            readings: readings()?,
+            frequency: value["freq"].as_f64().unwrap_or(0.0) as f32,
+            lemmas: Index::build_lemmas(&value, &word),
+            word,
+        }),
+        Some("remove") => Ok(Override::Remove { word }),
+        _ => Err(String::from("action should be fix, add or remove")),
+    }
+}
+
+/** Overrides of the file, none if there is no file. Lines that cannot be parsed are logged and skipped */
+pub(crate) fn load(path: &str) -> Vec<Override> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return vec![];
+    };
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(i, line)| match parse(line) {
+            Ok(o) => Some(o),
+            Err(msg) => {
+                println!("{}:{}: override skipped, {}", path, i + 1, msg);
+                None
+            }
+        })
+        .collect()
+}
+
+/** Apply the overrides to the index in order, logging each of them */
+pub(crate) fn apply(index: &mut Index, overrides: &[Override]) {
+    if overrides.is_empty() {
+        return;
+    }
+    let mut word_ids = index.word_ids_by_string();
+    for o in overrides {
+        match o {
+            Override::Fix { word, readings } => match word_ids.get(word).filter(|w| !index.removed_words.contains(w)) {
+                Some(&id) => {
+                    index.set_readings(id, readings.clone());
+                    println!("Override: {} fixed to {:?}", word, readings_pos(readings));
+                }
+                None => println!("Override: cannot fix {}, it is not in the index", word),
+            },
+            Override::Add { word, readings, frequency, lemmas } => {
+                let id = match word_ids.get(word) {
+                    Some(id) if !index.removed_words.contains(id) => {
+                        println!("Override: cannot add {}, it is already in the index", word);
+                        continue;
+                    }
+                    Some(&id) => {
+                        index.removed_words.remove(&id);
+                        index.set_readings(id, readings.clone());
+                        index.word_defs.frequencies[id as usize] = *frequency;
+                        id
+                    }
+                    None => {
+                        let id = index.word_defs.len() as WordId;
+                        word_ids.insert(word.clone(), id);
+                        index.push_homographs(word, readings.clone(), *frequency);
+                        id
+                    }
+                };
+                for lemma in lemmas {
+                    index.add_lemma_form(lemma, id);
+                }
+                println!("Override: {} added as {:?}", word, readings_pos(readings));
+            }
+            Override::Remove { word } => match word_ids.get(word).filter(|w| !index.removed_words.contains(w)) {
+                Some(&id) => {
+                    index.removed_words.insert(id);
+                    println!("Override: {} removed", word);
+                }
+                None => println!("Override: cannot remove {}, it is not in the index", word),
+            },
+        }
+    }
+    index.finalize();
+}
+
+fn readings_pos(readings: &WordReadings) -> Vec<String> {
+    readings.iter().map(|(pos, _, _)| format!("{:?}", pos)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{test_index, word_id};
+    use crate::letter_classes::ROOT_CLASSES;
+    use crate::{str_to_u8, u8_to_str, MatchMode, PosTag};
+
+    #[test]
+    fn overrides_fix_add_and_remove_words() {
+        let mut index = test_index();
+        let lines = [
+            r#"{"action": "fix", "word": "lit", "pos": "VERB", "morph": [{"Person": "3"}]}"#,
+            r#"{"action": "add", "word": "lu", "lemma": "lire", "readings": [{"pos": "VERB", "morph": []}], "freq": 2.5}"#,
+            r#"{"action": "remove", "word": "nul"}"#,
+            r#"{"action": "add", "word": "nul", "pos": "NOUN", "morph": []}"#,
+        ];
+        let overrides: Vec<Override> = lines.iter().map(|l| parse(l).unwrap()).collect();
+        assert!(parse(r#"{"action": "fix", "word": "lit", "pos": "VERBE", "morph": []}"#).is_err());
+        assert!(parse(r#"{"action": "rename", "word": "lit"}"#).is_err());
+
+        apply(&mut index, &overrides[..3]);
+        assert_eq!(index.word_pos(word_id(&index, "lit")), PosTag::VERB);
+        assert_eq!(index.word_defs.frequencies[word_id(&index, "lu") as usize], 2.5);
+        let all_words: Vec<WordId> = (0..index.word_defs.len() as WordId).collect();
+        assert_eq!(index.words_matching("lire", MatchMode::Lemma, &ROOT_CLASSES, &all_words), vec![word_id(&index, "lu")]);
+        let mut letters = str_to_u8("nullitlu");
+        letters.sort();
+        let matchable: Vec<String> = index
+            .filter_matchable_words(&letters, &ROOT_CLASSES)
+            .iter()
+            .map(|&w| u8_to_str(index.word_original_letters(w)))
+            .collect();
+        assert_eq!(matchable, vec!["il", "un", "lu", "lit"]);
+
+        apply(&mut index, &overrides[3..]);
+        assert!(!index.removed_words.contains(&word_id(&index, "nul")));
+        assert_eq!(index.word_pos(word_id(&index, "nul")), PosTag::NOUN);
+    }
+}
@@ -0,0 +1,140 @@
+/**
+ * Language model over the POS tags of an expression, built from the n-gram counts of `POS_N_GRAMS_PATH`.
+ *
+ * The probability of each tag given the previous ones is estimated from the longest n-gram seen in the corpus,
+ * backing off to shorter contexts with a fixed discount ("stupid backoff", Brants et al. 2007), down to the
+ * frequency of the tag alone, which is add-one smoothed. An expression is scored by the geometric mean of the
+ * probabilities of its tags, each one looked up with a sliding window of at most `order` tags: a rare transition
+ * lowers the score of the expression without zeroing it, and expressions of any length can be compared.
+ */
+use crate::{PosTag, MAX_EXPR_SIZE};
+use rustc_hash::FxHashMap;
+use serde_derive::{Deserialize, Serialize};
+
+/** Multiplies the probability each time the context is shortened */
+const BACKOFF_DISCOUNT: f32 = 0.4;
+/** Number of `PosTag` variants, for the add-one smoothing of the tag frequencies */
+const NB_POS_TAGS: usize = PosTag::SPACE as usize + 1;
+const BITS_PER_TAG: usize = 5;
+const _: () = assert!(NB_POS_TAGS < 1 << BITS_PER_TAG && MAX_EXPR_SIZE * BITS_PER_TAG <= 64);
+
+/** Up to `MAX_EXPR_SIZE` POS tags packed in an integer, the first tag in the lowest bits */
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct PosTagNGram(u64);
+
+impl PosTagNGram {
+    pub(crate) fn new(tags: &[PosTag]) -> PosTagNGram {
+        debug_assert!(tags.len() <= MAX_EXPR_SIZE);
+        // 0 is left for the end of the n-gram, so that n-grams of different sizes never collide
+        PosTagNGram(tags.iter().rev().fold(0, |packed, &tag| packed << BITS_PER_TAG | (tag as u64 + 1)))
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub(crate) struct PosNGramModel {
+    /** Occurences of the n-grams of at least 2 tags */
+    counts: FxHashMap<PosTagNGram, f32>,
+    /** Occurences of each tag, indexed by `PosTag as usize`, derived from the bigrams */
+    unigrams: Vec<f32>,
+    nb_unigrams: f32,
+    /** Size of the longest n-gram */
+    order: usize,
+}
+
+impl PosNGramModel {
+    /** N-grams longer than `MAX_EXPR_SIZE` are skipped. `finalize` must be called once they are all inserted */
+    pub(crate) fn insert(&mut self, tags: &[PosTag], occurences: f32) {
+        if tags.len() < 2 || tags.len() > MAX_EXPR_SIZE {
+            return;
+        }
+        *self.counts.entry(PosTagNGram::new(tags)).or_default() += occurences;
+        self.order = self.order.max(tags.len());
+    }
+
+    /** Derive the tag frequencies from the bigrams */
+    pub(crate) fn finalize(&mut self) {
+        self.unigrams = vec![0.0; NB_POS_TAGS];
+        for first in 0..NB_POS_TAGS {
+            for second in 0..NB_POS_TAGS {
+                let bigram = PosTagNGram(((second as u64 + 1) << BITS_PER_TAG) | (first as u64 + 1));
+                self.unigrams[first] += self.counts.get(&bigram).copied().unwrap_or(0.0);
+            }
+        }
+        self.nb_unigrams = self.unigrams.iter().sum();
+    }
+
+    pub(crate) fn count(&self, tags: &[PosTag]) -> f32 {
+        match tags.len() {
+            0 => self.nb_unigrams,
+            1 => self.unigrams.get(tags[0] as usize).copied().unwrap_or(0.0),
+            _ => self.counts.get(&PosTagNGram::new(tags)).copied().unwrap_or(0.0),
+        }
+    }
+
+    fn tag_probability(&self, tag: PosTag) -> f32 {
+        (self.count(&[tag]) + 1.0) / (self.nb_unigrams + NB_POS_TAGS as f32)
+    }
+
+    /** Probability of the last tag of `tags` given the previous ones */
+    pub(crate) fn conditional_probability(&self, tags: &[PosTag]) -> f32 {
+        let mut discount = 1.0;
+        for start in 0..tags.len() - 1 {
+            let occurences = self.count(&tags[start..]);
+            let context_occurences = self.count(&tags[start..tags.len() - 1]);
+            if occurences > 0.0 && context_occurences > 0.0 {
+                return discount * (occurences / context_occurences).min(1.0);
+            }
+            discount *= BACKOFF_DISCOUNT;
+        }
+        discount * self.tag_probability(tags[tags.len() - 1])
+    }
+
+    /** Probability of the last tag given at most `order - 1` previous ones */
+    pub(crate) fn last_probability(&self, tags: &[PosTag]) -> f32 {
+        self.conditional_probability(&tags[tags.len().saturating_sub(self.order.max(1))..])
+    }
+
+    /** Probability of each tag given at most `order - 1` previous ones */
+    pub(crate) fn sliding_probabilities(&self, tags: &[PosTag]) -> Vec<f32> {
+        (0..tags.len()).map(|i| self.last_probability(&tags[..=i])).collect()
+    }
+
+    /** Geometric mean of the sliding probabilities */
+    pub(crate) fn score(&self, tags: &[PosTag]) -> f32 {
+        let probabilities = self.sliding_probabilities(tags);
+        let log_sum: f32 = probabilities.iter().map(|p| p.ln()).sum();
+        (log_sum / probabilities.len() as f32).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PosTag::*;
+
+    #[test]
+    fn unseen_transitions_back_off() {
+        let mut model = PosNGramModel::default();
+        model.insert(&[DET, NOUN], 80.0);
+        model.insert(&[DET, ADJ], 20.0);
+        model.insert(&[NOUN, ADJ], 50.0);
+        model.insert(&[DET, NOUN, ADJ], 40.0);
+        model.finalize();
+        assert_eq!(model.order, 3);
+        assert_ne!(PosTagNGram::new(&[DET]), PosTagNGram::new(&[DET, ADJ]));
+        assert_eq!(model.conditional_probability(&[DET, NOUN]), 0.8);
+        assert_eq!(model.conditional_probability(&[DET, NOUN, ADJ]), 0.5);
+        /* NOUN NOUN was never seen, backs off to the frequency of NOUN */
+        let unseen = model.conditional_probability(&[DET, NOUN, NOUN]);
+        assert!(unseen > 0.0 && unseen < 0.4 * 0.4);
+        /* Sliding window of 3 tags */
+        assert_eq!(
+            model.sliding_probabilities(&[DET, NOUN, ADJ, ADJ])[3],
+            model.conditional_probability(&[NOUN, ADJ, ADJ])
+        );
+        /* One rare transition lowers the score without collapsing it */
+        let good = model.score(&[DET, NOUN, ADJ]);
+        let bad = model.score(&[DET, NOUN, NOUN, ADJ]);
+        assert!(bad < good && bad > good / 10.0);
+    }
+}
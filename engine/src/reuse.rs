@@ -0,0 +1,95 @@
+/**
+ * Words of an anagram taken from its input: an anagram of "le marquis de sade" still containing "le" and "de", or
+ * "marquise", feels like cheating. Letters are compared without their diacritics.
+ */
+use crate::letter_classes::ROOT_CLASSES;
+use crate::{u8_to_str, Index, WordId};
+use serde_derive::Serialize;
+
+/** Shorter common substrings are too frequent to be noticed */
+const MIN_SHARED_SUBSTRING_SIZE: usize = 4;
+pub(crate) const DEFAULT_REUSE_PENALTY: f32 = 0.5;
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub(crate) struct InputReuse {
+    /** Words of the anagram that are also words of the input */
+    shared_words: Vec<String>,
+    /** Longest substring of at least `MIN_SHARED_SUBSTRING_SIZE` letters shared with an input word, for the other words */
+    shared_substrings: Vec<String>,
+}
+
+impl InputReuse {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.shared_words.is_empty() && self.shared_substrings.is_empty()
+    }
+
+    /** Multiplies the score of the anagram, `penalty` being applied once per reused word or substring */
+    pub(crate) fn factor(&self, penalty: f32) -> f32 {
+        penalty.powi((self.shared_words.len() + self.shared_substrings.len()) as i32)
+    }
+}
+
+fn letters_equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(&x, &y)| ROOT_CLASSES.equal(x, y))
+}
+
+/** Longest common substring, as a range of `a` */
+fn longest_common_substring(a: &[u8], b: &[u8]) -> (usize, usize) {
+    let mut best = (0, 0);
+    // lengths[j + 1]: length of the common suffix of a[..=i] and b[..=j]
+    let mut lengths = vec![0; b.len() + 1];
+    for (i, &a_letter) in a.iter().enumerate() {
+        for j in (0..b.len()).rev() {
+            lengths[j + 1] = if ROOT_CLASSES.equal(a_letter, b[j]) { lengths[j] + 1 } else { 0 };
+            if lengths[j + 1] > best.1 - best.0 {
+                best = (i + 1 - lengths[j + 1], i + 1);
+            }
+        }
+    }
+    best
+}
+
+/** `input_words` are the encoded letters of each word of the input */
+pub(crate) fn input_reuse(words: &[WordId], index: &Index, input_words: &[Vec<u8>]) -> InputReuse {
+    let mut reuse = InputReuse::default();
+    let mut used_input_words = vec![false; input_words.len()];
+    for &word in words {
+        let letters = index.word_original_letters(word);
+        let same_word = (0..input_words.len()).find(|&i| !used_input_words[i] && letters_equal(letters, &input_words[i]));
+        if let Some(i) = same_word {
+            used_input_words[i] = true;
+            reuse.shared_words.push(u8_to_str(letters));
+            continue;
+        }
+        let (start, end) = input_words
+            .iter()
+            .map(|input_word| longest_common_substring(letters, input_word))
+            .max_by_key(|(start, end)| end - start)
+            .unwrap_or((0, 0));
+        if end - start >= MIN_SHARED_SUBSTRING_SIZE {
+            reuse.shared_substrings.push(u8_to_str(&letters[start..end]));
+        }
+    }
+    reuse
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{test_index, word_id};
+    use crate::{str_to_u8, PosTag};
+
+    #[test]
+    fn shared_words_and_substrings() {
+        let mut index = test_index();
+        index.push_word("marquise", PosTag::NOUN, vec![], 0.0);
+        let input: Vec<Vec<u8>> = ["le", "marquis", "de", "sadé"].iter().map(|w| str_to_u8(w)).collect();
+
+        let reuse = input_reuse(&["le", "lit", "le", "marquise"].map(|w| word_id(&index, w)), &index, &input);
+        assert_eq!(reuse.shared_words, vec!["le"]);
+        assert_eq!(reuse.shared_substrings, vec!["marquis"]);
+        assert_eq!(reuse.factor(0.5), 0.25);
+        assert!(input_reuse(&[word_id(&index, "lit"), word_id(&index, "nul")], &index, &input).is_empty());
+        assert_eq!(longest_common_substring(&str_to_u8("sade"), &str_to_u8("ades")), (1, 4));
+    }
+}
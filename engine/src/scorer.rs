@@ -0,0 +1,1239 @@
+/**
+ * Ranking of the anagrams. A scorer gives a score to a list of words in a given order, and finds the best orderings
+ * of a set of words. Scorers are registered in `SCORERS` and picked by name, so that new ranking ideas can be tried
+ * alongside the current one.
+ */
+use crate::agreement::{self, Violation};
+use crate::pos_model::PosTagNGram;
+use crate::{Index, PosMorph, PosTag, ReadingId, ReadingSetId, VerbForm, WordId, u8_to_str};
+use itertools::Itertools;
+use rustc_hash::FxHashMap;
+use serde_derive::Serialize;
+
+pub(crate) const DEFAULT_SCORER: &str = "heuristic";
+pub(crate) static SCORERS: &[&dyn Scorer] = &[&HeuristicScorer];
+
+pub(crate) fn scorer_by_name(name: &str) -> Option<&'static dyn Scorer> {
+    SCORERS.iter().find(|scorer| scorer.name() == name).copied()
+}
+
+/**
+ * Up to this number of words, the default `Scorer::best_orderings` scores all the permutations. Above it, up to
+ * `MAX_EXPR_SIZE` words, the orderings are searched: see `anagramdr bench` for their time
+ */
+pub(crate) const EXHAUSTIVE_ORDERING_SIZE: usize = 6;
+/** Number of partial orderings kept at each step by the default `Scorer::best_orderings` above that */
+const ORDERING_BEAM_WIDTH: usize = 64;
+
+pub(crate) trait Scorer: Sync {
+    fn name(&self) -> &'static str;
+
+    /**
+     * Score of at least 2 words in this order, the higher the better. Whatever the scorer, the anagrams are then
+     * ranked by this score times the factors depending on the query, see `ScoreBreakdown::with_query_factor`.
+     */
+    fn score(&self, ordered_words: &[WordId], index: &Index) -> f32;
+
+    /** The `k` best orderings of at least 2 words, best first, as positions in `words`, with their score */
+    fn best_orderings(&self, words: &[WordId], index: &Index, k: usize) -> Vec<(Vec<usize>, f32)> {
+        self.best_kept_orderings(words, index, k, &|_| true)
+    }
+
+    /**
+     * As `best_orderings`, among the orderings of the words for which `keep` is true: the search goes on until `k` of
+     * them are found or there are no orderings left.
+     * All the permutations are scored for small expressions, a beam search over the prefixes is used above, with
+     * `keep` checked on the complete orderings only, so that it can miss the kept orderings.
+     */
+    fn best_kept_orderings(
+        &self,
+        words: &[WordId],
+        index: &Index,
+        k: usize,
+        keep: &dyn Fn(&[WordId]) -> bool,
+    ) -> Vec<(Vec<usize>, f32)> {
+        let n = words.len();
+        if n <= EXHAUSTIVE_ORDERING_SIZE {
+            return exhaustive_orderings(words, k, keep, |perm| {
+                self.score(&perm.iter().map(|&i| words[i]).collect_vec(), index)
+            });
+        }
+        let beam_width = ORDERING_BEAM_WIDTH.max(k);
+        let mut beam: Vec<(Vec<usize>, f32)> = (0..n).permutations(2).map(|prefix| (prefix, 0.0)).collect();
+        for size in 3..=n {
+            let mut next_beam = Vec::with_capacity(beam_width + 1);
+            for (prefix, _) in &beam {
+                for i in (0..n).filter(|i| !prefix.contains(i)) {
+                    let mut extended = prefix.clone();
+                    extended.push(i);
+                    let ordered = extended.iter().map(|&i| words[i]).collect_vec();
+                    if size == n && !keep(&ordered) {
+                        continue;
+                    }
+                    let score = self.score(&ordered, index);
+                    insert_k_best(&mut next_beam, if size == n { k } else { beam_width }, (extended, score), |o| o.1);
+                }
+            }
+            beam = next_beam;
+        }
+        beam
+    }
+
+    /** Breakdown of the score, for the scorers that support it */
+    fn explain(&self, _ordered_words: &[WordId], _index: &Index) -> Option<ScoreBreakdown> {
+        None
+    }
+}
+
+/**
+ * The `k` best permutations of `words` by `score` for which `keep` is true, best first, the first found winning on
+ * equal scores. `score` is given the positions of the words.
+ */
+fn exhaustive_orderings(
+    words: &[WordId],
+    k: usize,
+    keep: &dyn Fn(&[WordId]) -> bool,
+    mut score: impl FnMut(&[usize]) -> f32,
+) -> Vec<(Vec<usize>, f32)> {
+    let n = words.len();
+    let mut best: Vec<(Vec<usize>, f32)> = Vec::with_capacity(k + 1);
+    for perm in (0..n).permutations(n) {
+        let score = score(&perm);
+        // Only the orderings which would be among the best ones need to be checked
+        if best.len() == k && best.last().is_some_and(|last| score <= last.1) {
+            continue;
+        }
+        if keep(&perm.iter().map(|&i| words[i]).collect_vec()) {
+            insert_k_best(&mut best, k, (perm, score), |o| o.1);
+        }
+    }
+    best
+}
+
+/** Multiplies the score for each agreement violation, see `agreement::violations` */
+const AGREEMENT_VIOLATION_FACTOR: f32 = 0.5;
+/**
+ * Agreement and the POS n-grams after `N_GRAM_PREFIX_SIZE` words span more than pairs of words, so they cannot be
+ * part of the dynamic programming: this number of orderings is found in addition to the asked ones, and all of them
+ * are reranked with the full score. The best ordering can still be missed.
+ */
+const RERANK_EXTRA: usize = 3;
+
+/**
+ * Most candidates of the dynamic programming reranked when too few are kept, see `Scorer::best_kept_orderings`: the
+ * kept orderings ranked lower by the dynamic programming are missed. The ranks of the chains are stored on a byte.
+ */
+const MAX_RERANKED_ORDERINGS: usize = u8::MAX as usize + 1;
+
+fn agreement_factor(violations: &[Violation]) -> f32 {
+    AGREEMENT_VIOLATION_FACTOR.powi(violations.len() as i32)
+}
+
+/**
+ * The original ranking: tagging stats of the pairs of consecutive words, POS n-gram of the first words, and
+ * penalties for the expressions ending badly, with agreement violations or made of a lot of small words.
+ */
+pub(crate) struct HeuristicScorer;
+
+impl Scorer for HeuristicScorer {
+    fn name(&self) -> &'static str {
+        DEFAULT_SCORER
+    }
+
+    fn score(&self, ordered_words: &[WordId], index: &Index) -> f32 {
+        let readings = best_readings(ordered_words, index);
+        full_score(
+            score_combination(ordered_words, &readings, index),
+            readings_probability(ordered_words, &readings, index),
+            expression_divisors(ordered_words, index),
+            context_factors(&readings, index),
+        )
+    }
+
+    /**
+     * Up to `EXHAUSTIVE_ORDERING_SIZE` words, the result is the one of the default implementation, scoring all the
+     * permutations: by an `OrderingSearch`, unless the homographs have too many combinations of readings.
+     * Above that, the candidates of the dynamic programming of `best_orderings` are reranked, which is a heuristic,
+     * and more candidates are found while too few of them are kept, up to `MAX_RERANKED_ORDERINGS`.
+     */
+    fn best_kept_orderings(
+        &self,
+        words: &[WordId],
+        index: &Index,
+        k: usize,
+        keep: &dyn Fn(&[WordId]) -> bool,
+    ) -> Vec<(Vec<usize>, f32)> {
+        let n = words.len();
+        if n <= EXHAUSTIVE_ORDERING_SIZE {
+            let nb_combinations = words.iter().fold(1usize, |nb, &w| nb.saturating_mul(index.word_readings(w).len()));
+            if nb_combinations > MAX_READING_COMBINATIONS {
+                return exhaustive_orderings(words, k, keep, |perm| {
+                    self.score(&perm.iter().map(|&i| words[i]).collect_vec(), index)
+                });
+            }
+            return OrderingSearch::new(words, index, k, keep).run();
+        }
+        let mut nb_candidates = k + RERANK_EXTRA;
+        loop {
+            let candidates = best_orderings(words, index, nb_candidates);
+            let is_last_search = candidates.len() < nb_candidates || nb_candidates >= MAX_RERANKED_ORDERINGS;
+            let mut orderings: Vec<(Vec<usize>, f32)> = candidates
+                .into_iter()
+                .filter_map(|(perm, _)| {
+                    let ordered = perm.iter().map(|&i| words[i]).collect_vec();
+                    keep(&ordered).then(|| (perm, self.score(&ordered, index)))
+                })
+                .collect();
+            // Stable, so that the first found wins on equal scores as in `best_orderings`
+            orderings.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+            orderings.truncate(k);
+            if orderings.len() == k || is_last_search {
+                return orderings;
+            }
+            nb_candidates = (nb_candidates * 2).min(MAX_RERANKED_ORDERINGS);
+        }
+    }
+
+    fn explain(&self, ordered_words: &[WordId], index: &Index) -> Option<ScoreBreakdown> {
+        Some(explain_score(ordered_words, index))
+    }
+}
+
+/** Slightly raises the bounds of `OrderingSearch`, which do not add the pair scores in the same order as the scores */
+const BOUND_MARGIN: f32 = 1.001;
+
+/** A combination of the readings of the words of a prefix, see `OrderingSearch` */
+#[derive(Clone, Copy)]
+struct ReadingsPrefix {
+    /** Reading of the last word, as a position in `OrderingSearch::nodes` */
+    node: usize,
+    /** Position of the combination of the readings of the previous words in the previous level */
+    parent: usize,
+    /** As `score_combination`, without the last word penalty */
+    pairs: f32,
+    /** As `readings_probability` */
+    probability: f32,
+    /** Sum of the logarithms of the probabilities of the tags, see `PosNGramModel::score` */
+    n_gram_log_sum: f32,
+    nb_violations: i32,
+}
+
+/**
+ * Search of the `k` best orderings of a few words by `HeuristicScorer::score`, the first found winning on equal scores
+ * as in `exhaustive_orderings`. The permutations are built word after word in the same order, each prefix holding
+ * every combination of the readings of its words with their partial scores: the probability of a POS tag and the
+ * agreement of a word depend on the previous words only, so they are computed once per prefix, not once per
+ * permutation. A prefix is dropped when it cannot lead to one of the best orderings found so far, given that the
+ * next pair scores are at most the ones of the best chain of the remaining words, the probabilities of their tags and
+ * readings at most 1, and that agreement violations only lower the score.
+ */
+struct OrderingSearch<'a> {
+    words: &'a [WordId],
+    index: &'a Index,
+    k: usize,
+    keep: &'a dyn Fn(&[WordId]) -> bool,
+    /** Each reading of each word, as the position of the word and the reading */
+    nodes: Vec<(usize, ReadingId)>,
+    /** Position in `nodes` of the first reading of each word, and after the last word */
+    first_nodes: Vec<usize>,
+    /** Pair score of the readings of two words, `nodes.len()` x `nodes.len()` */
+    pair_scores: Vec<f32>,
+    /** Probability of each reading, relative to the main reading of its word */
+    probabilities: Vec<f32>,
+    /**
+     * Best sum of the pair scores of the chains of words starting with a given word and going through all the words
+     * of a set (the first one included), whatever their readings, at `set * words.len() + first`
+     */
+    best_chains: Vec<f32>,
+    divisors: (f32, f32),
+    /** The combinations of readings of each prefix of `permutation`, by the size of the prefix minus 1 */
+    levels: Vec<Vec<ReadingsPrefix>>,
+    permutation: Vec<usize>,
+    /** Readings and tags of the prefix being extended */
+    readings: Vec<ReadingId>,
+    tags: Vec<PosTag>,
+    best: Vec<(Vec<usize>, f32)>,
+}
+
+impl<'a> OrderingSearch<'a> {
+    fn new(words: &'a [WordId], index: &'a Index, k: usize, keep: &'a dyn Fn(&[WordId]) -> bool) -> Self {
+        let n = words.len();
+        let nodes = words
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &w)| index.word_readings(w).iter().map(move |&r| (i, r)))
+            .collect_vec();
+        let mut first_nodes = vec![0];
+        first_nodes.extend(words.iter().scan(0, |end, &w| {
+            *end += index.word_readings(w).len();
+            Some(*end)
+        }));
+        let nb_nodes = nodes.len();
+        let pair_scores: Vec<f32> = (0..nb_nodes * nb_nodes)
+            .map(|i| {
+                let ((first, first_reading), (second, second_reading)) = (nodes[i / nb_nodes], nodes[i % nb_nodes]);
+                pair_score_of_readings(index, (words[first], words[second]), (first_reading, second_reading))
+            })
+            .collect();
+        let best_pair_scores: Vec<f32> = (0..n * n)
+            .map(|i| {
+                (first_nodes[i / n]..first_nodes[i / n + 1])
+                    .cartesian_product(first_nodes[i % n]..first_nodes[i % n + 1])
+                    .map(|(first, second)| pair_scores[first * nb_nodes + second])
+                    .fold(0.0, f32::max)
+            })
+            .collect();
+        let mut best_chains = vec![0.0; (1 << n) * n];
+        for set in 1..1 << n {
+            for first in (0..n).filter(|&i| set & (1 << i) != 0) {
+                let rest = set & !(1 << first);
+                best_chains[set * n + first] = (0..n)
+                    .filter(|&second| rest & (1 << second) != 0)
+                    .map(|second| best_pair_scores[first * n + second] + best_chains[rest * n + second])
+                    .fold(0.0, f32::max);
+            }
+        }
+        OrderingSearch {
+            words,
+            index,
+            k,
+            keep,
+            probabilities: nodes.iter().map(|&(i, r)| index.reading_probability(words[i], r)).collect(),
+            nodes,
+            first_nodes,
+            pair_scores,
+            best_chains,
+            divisors: expression_divisors(words, index),
+            levels: vec![vec![]; n],
+            permutation: Vec::with_capacity(n),
+            readings: Vec::with_capacity(n),
+            tags: Vec::with_capacity(n),
+            best: Vec::with_capacity(k + 1),
+        }
+    }
+
+    fn run(mut self) -> Vec<(Vec<usize>, f32)> {
+        self.extend();
+        self.best
+    }
+
+    /** Try each remaining word after the current prefix */
+    fn extend(&mut self) {
+        let n = self.words.len();
+        for word in 0..n {
+            if self.permutation.contains(&word) {
+                continue;
+            }
+            self.permutation.push(word);
+            self.push_level(word);
+            if self.permutation.len() == n {
+                self.score_permutation();
+            } else if self.can_beat_best() {
+                self.extend();
+            }
+            self.permutation.pop();
+        }
+    }
+
+    /** Compute the level of the prefix ending with `word`, from the level of the prefix before it */
+    fn push_level(&mut self, word: usize) {
+        let depth = self.permutation.len() - 1;
+        let mut level = std::mem::take(&mut self.levels[depth]);
+        level.clear();
+        let nb_parents = if depth == 0 { 1 } else { self.levels[depth - 1].len() };
+        for parent in 0..nb_parents {
+            /* Readings of the previous words, in order */
+            self.readings.resize(depth, 0);
+            let mut position = parent;
+            for d in (0..depth).rev() {
+                let prefix = self.levels[d][position];
+                self.readings[d] = self.nodes[prefix.node].1;
+                position = prefix.parent;
+            }
+            for node in self.first_nodes[word]..self.first_nodes[word + 1] {
+                self.readings.truncate(depth);
+                self.readings.push(self.nodes[node].1);
+                self.tags.clear();
+                self.tags.extend(self.readings.iter().map(|&r| self.index.reading_pos(r)));
+                let tag_probability = self.index.pos_n_grams.last_probability(&self.tags);
+                let nb_violations = agreement::last_word_violations(&self.readings, self.index).len() as i32;
+                let previous = if depth == 0 {
+                    ReadingsPrefix { node, parent, pairs: 0.0, probability: 1.0, n_gram_log_sum: 0.0, nb_violations: 0 }
+                } else {
+                    let previous = self.levels[depth - 1][parent];
+                    ReadingsPrefix {
+                        pairs: previous.pairs + self.pair_scores[previous.node * self.nodes.len() + node],
+                        ..previous
+                    }
+                };
+                level.push(ReadingsPrefix {
+                    node,
+                    parent,
+                    probability: previous.probability * self.probabilities[node],
+                    n_gram_log_sum: previous.n_gram_log_sum + tag_probability.ln(),
+                    nb_violations: previous.nb_violations + nb_violations,
+                    ..previous
+                });
+            }
+        }
+        self.levels[depth] = level;
+    }
+
+    /** As `context_factors`, once all the words are there */
+    fn context_factors(&self, prefix: &ReadingsPrefix) -> (f32, f32) {
+        let n_gram_factor = N_GRAM_SCALE * (prefix.n_gram_log_sum / self.words.len() as f32).exp();
+        (n_gram_factor, AGREEMENT_VIOLATION_FACTOR.powi(prefix.nb_violations))
+    }
+
+    /** Whether a permutation starting with the current prefix can be among the best ones found so far */
+    fn can_beat_best(&self) -> bool {
+        let Some(to_beat) = self.best.last().filter(|_| self.best.len() == self.k).map(|last| last.1) else {
+            return true;
+        };
+        let n = self.words.len();
+        let last = *self.permutation.last().unwrap();
+        let rest = self.permutation.iter().fold((1 << n) - 1, |set, &i| set & !(1 << i)) | (1 << last);
+        let next_pairs = self.best_chains[rest * n + last];
+        self.levels[self.permutation.len() - 1].iter().any(|prefix| {
+            let context = self.context_factors(prefix);
+            full_score(prefix.pairs + next_pairs, prefix.probability, self.divisors, context) * BOUND_MARGIN > to_beat
+        })
+    }
+
+    /** As `best_readings` then `HeuristicScorer::score`, the combinations of readings being in the same order */
+    fn score_permutation(&mut self) {
+        let mut best: (f32, f32, f32, (f32, f32)) = (f32::MIN, 0.0, 0.0, (0.0, 0.0));
+        for prefix in &self.levels[self.words.len() - 1] {
+            // As `score_combination`
+            let mut combination = prefix.pairs;
+            if is_penalized_last_word(self.index.reading_pos(self.nodes[prefix.node].1)) {
+                combination /= 4.0;
+            }
+            let context = self.context_factors(prefix);
+            let score = combination * context.0 * prefix.probability * context.1;
+            if score > best.0 {
+                best = (score, combination, prefix.probability, context);
+            }
+        }
+        let (_, combination, probability, context) = best;
+        let score = full_score(combination, probability, self.divisors, context);
+        if self.best.len() == self.k && self.best.last().is_some_and(|last| score <= last.1) {
+            return;
+        }
+        if (self.keep)(&self.permutation.iter().map(|&i| self.words[i]).collect_vec()) {
+            insert_k_best(&mut self.best, self.k, (self.permutation.clone(), score), |o| o.1);
+        }
+    }
+}
+
+/** Factors of the score spanning the whole expression read with these readings: n-gram factor and agreement factor */
+fn context_factors(readings: &[ReadingId], index: &Index) -> (f32, f32) {
+    (n_gram_factor(readings, index), agreement_factor(&agreement::violations(readings, index)))
+}
+
+/**
+ * `HeuristicScorer::score`, given the `score_combination` and `readings_probability` of the readings, the
+ * `expression_divisors` and the `context_factors`
+ */
+fn full_score(
+    combination: f32,
+    readings_probability: f32,
+    (length_normalisation, small_words_penalty): (f32, f32),
+    (n_gram_factor, agreement_factor): (f32, f32),
+) -> f32 {
+    // Same operations in the same order as `score_ordering`, so that the scores are the same to the last digit
+    combination * n_gram_factor * readings_probability / length_normalisation / small_words_penalty * agreement_factor
+}
+
+/** Multiplies the tagging stat of a pair matching a rule of `morph_pair_factor` */
+const VERB_FORM_BONUS: f32 = 1.5;
+/** Multiplies the tagging stat of a pair breaking a rule of `morph_pair_factor` */
+const VERB_FORM_PENALTY: f32 = 0.5;
+
+/**
+ * Weight of the tagging stat of two consecutive readings, for what the stats cannot see as they do not record the
+ * verb forms: a preposition is followed by an infinitive ("pour manger") rather than a finite verb, and a pronoun
+ * with a person, most likely the subject, by a finite verb ("il mange").
+ */
+pub(crate) fn morph_pair_factor(first: &PosMorph, second: &PosMorph) -> f32 {
+    if !matches!(second.pos, PosTag::VERB | PosTag::AUX) {
+        return 1.0;
+    }
+    match (first.pos, second.morph.verb_form) {
+        (PosTag::ADP, Some(VerbForm::Inf)) => VERB_FORM_BONUS,
+        (PosTag::ADP, Some(VerbForm::Fin)) => VERB_FORM_PENALTY,
+        (PosTag::PRON, Some(VerbForm::Fin)) if first.morph.person.is_some() => VERB_FORM_BONUS,
+        _ => 1.0,
+    }
+}
+
+/** Weight of the POS bigram stat (square root, as the tagging stats) for the pairs of morphologies never seen */
+const UNSEEN_PAIR_BACKOFF: f32 = 0.1;
+
+/** Tagging stat of two morphologies, backing off to their POS tags if never seen, weighted by `morph_pair_factor` */
+pub(crate) fn morph_pair_score(index: &Index, first: &PosMorph, second: &PosMorph) -> f32 {
+    let key = (
+        PosMorph { morph: first.morph.tagging_stats_key(), ..*first },
+        PosMorph { morph: second.morph.tagging_stats_key(), ..*second },
+    );
+    let occ = match index.tagging_stats.get(&key) {
+        Some(&occ) => occ,
+        None => UNSEEN_PAIR_BACKOFF * index.pos_n_grams.count(&[first.pos, second.pos]).sqrt(),
+    };
+    occ * morph_pair_factor(first, second)
+}
+
+/**
+ * Scores of the pairs of readings by `HeuristicScorer`: the best tagging stat of their morphologies, backing off to
+ * the stat of their POS tags when lower, weighted by the best `morph_pair_factor` of their morphologies. They are
+ * computed from `Index::reading_pair_stats` once the index is built or loaded and are not saved with it, so that the
+ * weights can change without building the index again.
+ */
+#[derive(Clone, Default, Debug, PartialEq)]
+pub(crate) struct PairScores {
+    nb_readings: usize,
+    /** Score of each pair of readings, `nb_readings` x `nb_readings` */
+    readings: Vec<f32>,
+    /**
+     * Best score of each reading set followed by each reading, over all the readings of the set whatever their
+     * probability, `Index::reading_sets.len()` x `nb_readings`: it grows as the reading sets, not as their pairs
+     */
+    reading_sets: Vec<f32>,
+}
+
+impl PairScores {
+    pub(crate) fn new(index: &Index) -> PairScores {
+        let nb_readings = index.readings.len();
+        let mut readings = Vec::with_capacity(nb_readings * nb_readings);
+        for first in 0..nb_readings as ReadingId {
+            for second in 0..nb_readings as ReadingId {
+                let pos_morphs = |reading: ReadingId| {
+                    let pos = index.reading_pos(reading);
+                    index.reading_morphs(reading).iter().map(move |&morph| PosMorph { morph, pos })
+                };
+                /* 0 when a reading has no morphology, as its stats */
+                let factor = pos_morphs(first)
+                    .cartesian_product(pos_morphs(second).collect_vec())
+                    .map(|(a, b)| morph_pair_factor(&a, &b))
+                    .fold(0.0, f32::max);
+                let pos_bigram = index.pos_n_grams.count(&[index.reading_pos(first), index.reading_pos(second)]);
+                let backoff = UNSEEN_PAIR_BACKOFF * pos_bigram.sqrt();
+                readings.push(index.reading_pair_stat(first, second).max(backoff) * factor);
+            }
+        }
+        let mut reading_sets = Vec::with_capacity(index.reading_sets.len() * nb_readings);
+        for first in &index.reading_sets {
+            for second in 0..nb_readings {
+                let best_score = first.iter().map(|&a| readings[a as usize * nb_readings + second]).fold(0.0, f32::max);
+                reading_sets.push(best_score);
+            }
+        }
+        PairScores { nb_readings, readings, reading_sets }
+    }
+
+    #[inline(always)]
+    pub(crate) fn of_readings(&self, first: ReadingId, second: ReadingId) -> f32 {
+        self.readings[first as usize * self.nb_readings + second as usize]
+    }
+
+    #[inline(always)]
+    pub(crate) fn of_reading_sets(&self, first: ReadingSetId, second: &[ReadingId]) -> f32 {
+        let after_first = &self.reading_sets[first as usize * self.nb_readings..][..self.nb_readings];
+        second.iter().map(|&b| after_first[b as usize]).fold(0.0, f32::max)
+    }
+}
+
+/** Weight of the occurences of a pair of words, added to the tagging stat of their readings */
+const WORD_BIGRAM_WEIGHT: f32 = 25.0;
+
+/**
+ * Score of two consecutive words: the tagging stat of their readings, plus the weighted occurences of the words
+ * themselves when the pair was seen in the corpus. Unseen pairs back off to the tagging stat only.
+ */
+#[inline(always)]
+fn with_word_bigram(index: &Index, first: WordId, second: WordId, pos_score: f32) -> f32 {
+    match index.word_bigram_score(first, second) {
+        Some(occs) => pos_score + WORD_BIGRAM_WEIGHT * occs,
+        None => pos_score,
+    }
+}
+
+/** Pair score with the best readings of both words for this pair, see `Index::reading_pair_score` */
+#[inline(always)]
+fn pair_score(index: &Index, first: WordId, second: WordId) -> f32 {
+    with_word_bigram(index, first, second, index.reading_pair_score(first, second))
+}
+
+/** Pair score with the given readings of both words */
+fn pair_score_of_readings(index: &Index, words: (WordId, WordId), readings: (ReadingId, ReadingId)) -> f32 {
+    with_word_bigram(index, words.0, words.1, index.pair_score_of_readings(readings.0, readings.1))
+}
+
+/** Up to this number of combinations of the readings of the words, `best_readings` tries all of them */
+const MAX_READING_COMBINATIONS: usize = 64;
+/** Number of partial combinations of readings kept for each reading of a word by `likely_readings` */
+const READING_PATHS: usize = 8;
+
+/**
+ * The readings of the words in this order maximizing their score, including the agreement factor: each homograph
+ * is read as what fits its neighbours best, weighted by the probability of the reading. All the combinations of
+ * readings are tried up to `MAX_READING_COMBINATIONS`, the most likely ones by their pairs above.
+ */
+pub(crate) fn best_readings(ordered_words: &[WordId], index: &Index) -> Vec<ReadingId> {
+    if ordered_words.iter().all(|&w| index.word_readings(w).len() == 1) {
+        return ordered_words.iter().map(|&w| index.word_readings(w)[0]).collect();
+    }
+    let nb_combinations = ordered_words.iter().fold(1usize, |n, &w| n.saturating_mul(index.word_readings(w).len()));
+    let combinations: Vec<Vec<ReadingId>> = if nb_combinations <= MAX_READING_COMBINATIONS {
+        ordered_words.iter().map(|&w| index.word_readings(w).iter().copied()).multi_cartesian_product().collect()
+    } else {
+        likely_readings(ordered_words, index)
+    };
+    let mut best: (Vec<ReadingId>, f32) = (vec![], f32::MIN);
+    for readings in combinations {
+        let score = score_ordering(ordered_words, &readings, index)
+            * agreement_factor(&agreement::violations(&readings, index));
+        if score > best.1 {
+            best = (readings, score);
+        }
+    }
+    best.0
+}
+
+/**
+ * Combinations of the readings of the words maximizing the sum of their pair scores times their probability, by a
+ * Viterbi search keeping the `READING_PATHS` best combinations ending with each reading of each word. The n-gram,
+ * the last word penalty and the agreement span more than pairs, they are left to `best_readings`.
+ */
+fn likely_readings(ordered_words: &[WordId], index: &Index) -> Vec<Vec<ReadingId>> {
+    /* (readings, sum of the pair scores, probability) of the best paths ending with each reading of the word */
+    let first = ordered_words[0];
+    let mut paths: Vec<Vec<(Vec<ReadingId>, f32, f32)>> = index
+        .word_readings(first)
+        .iter()
+        .map(|&r| vec![(vec![r], 0.0, index.reading_probability(first, r))])
+        .collect();
+    for words in ordered_words.windows(2) {
+        let (previous, word) = (words[0], words[1]);
+        paths = index
+            .word_readings(word)
+            .iter()
+            .map(|&r| {
+                let probability = index.reading_probability(word, r);
+                let mut best = Vec::with_capacity(READING_PATHS + 1);
+                for (readings, pairs, path_probability) in paths.iter().flatten() {
+                    let pairs = pairs + pair_score_of_readings(index, (previous, word), (*readings.last().unwrap(), r));
+                    let path_probability = path_probability * probability;
+                    let mut extended = readings.clone();
+                    extended.push(r);
+                    insert_k_best(&mut best, READING_PATHS, (extended, pairs, path_probability), |p| p.1 * p.2);
+                }
+                best
+            })
+            .collect();
+    }
+    paths.into_iter().flatten().map(|(readings, _, _)| readings).collect()
+}
+
+/** Number of words at the start of an expression whose POS n-gram score is part of the dynamic programming */
+const N_GRAM_PREFIX_SIZE: usize = 4;
+/** Multiplies the POS n-gram score, a mean probability, to keep the scores in the range of the pair scores */
+const N_GRAM_SCALE: f32 = 100.0;
+
+fn n_gram_factor(readings: &[ReadingId], index: &Index) -> f32 {
+    let tags = readings.iter().map(|&r| index.reading_pos(r)).collect_vec();
+    N_GRAM_SCALE * index.pos_n_grams.score(&tags)
+}
+
+/** Product of the probabilities of the readings, relative to the main readings */
+fn readings_probability(ordered_words: &[WordId], readings: &[ReadingId], index: &Index) -> f32 {
+    ordered_words.iter().zip(readings).map(|(&w, &r)| index.reading_probability(w, r)).product()
+}
+
+/** A chain of words, see `best_orderings` */
+#[derive(Clone, Copy)]
+struct Chain {
+    score: f32,
+    /** Second word of the chain */
+    second: u8,
+    /** Rank of the rest of the chain, among the chains starting with `second` */
+    rest_rank: u8,
+}
+
+/** Insert `item` into `best`, sorted by decreasing score and holding at most `k` items. Returns false if it was not kept */
+fn insert_k_best<T>(best: &mut Vec<T>, k: usize, item: T, score: impl Fn(&T) -> f32) -> bool {
+    let item_score = score(&item);
+    // Equal scores are inserted after the existing ones, so that the first found wins
+    let position = best.iter().position(|other| score(other) < item_score).unwrap_or(best.len());
+    if position >= k {
+        return false;
+    }
+    best.insert(position, item);
+    best.truncate(k);
+    true
+}
+
+/**
+ * Find the `k` orderings of `words` maximizing `prefix_score_ordering`, without scoring all the permutations.
+ *
+ * The score is the sum of the pair scores along the expression, divided by a penalty depending on the last word,
+ * multiplied by the n-gram factor of the first `N_GRAM_PREFIX_SIZE` words. So for each possible prefix, only the
+ * best ways to chain the remaining words are needed: they are computed once for all prefixes by dynamic programming
+ * over the sets of remaining words (as in the Held-Karp algorithm), keeping the `k` best chains and keeping apart
+ * the chains ending with a penalized word. This stays cheap for expressions of 10 words, and is exact for
+ * `prefix_score_ordering` only: it ignores the n-gram after the first words, the agreement and the readings in
+ * context, so it is a heuristic for `HeuristicScorer::score`.
+ * Returns the positions of the words in `words`, in order, and the score, best first.
+ */
+fn best_orderings(words: &[WordId], index: &Index, k: usize) -> Vec<(Vec<usize>, f32)> {
+    let n = words.len();
+    if n <= N_GRAM_PREFIX_SIZE {
+        // The n-gram covers the whole expression, there are at most 24 permutations
+        let mut best: Vec<(Vec<usize>, f32)> = Vec::with_capacity(k + 1);
+        (0..n).permutations(n).for_each(|perm| {
+            let ordered: Vec<WordId> = perm.iter().map(|&i| words[i]).collect();
+            let score = prefix_score_ordering(&ordered, index);
+            insert_k_best(&mut best, k, (perm, score), |o| o.1);
+        });
+        return best;
+    }
+    let pair_scores: Vec<f32> = (0..n * n)
+        .map(|i| pair_score(index, words[i / n], words[i % n]))
+        .collect();
+    let penalized: Vec<bool> = words.iter().map(|&w| is_penalized_last_word(index.word_pos(w))).collect();
+
+    /* chains[p][set * n + first]: the k best chains starting with `first` and going through all the words of `set`
+    (`first` included), ending with a penalized word if p is 1. Their score is the sum of their pair scores */
+    let nb_sets = 1 << n;
+    let mut chains: [Vec<Vec<Chain>>; 2] = [vec![vec![]; nb_sets * n], vec![vec![]; nb_sets * n]];
+    for set in 1..nb_sets {
+        for first in (0..n).filter(|&i| set & (1 << i) != 0) {
+            let rest = set & !(1 << first);
+            if rest == 0 {
+                chains[penalized[first] as usize][set * n + first].push(Chain { score: 0.0, second: u8::MAX, rest_rank: 0 });
+                continue;
+            }
+            for chains in chains.iter_mut() {
+                let mut best = Vec::with_capacity(k + 1);
+                for second in (0..n).filter(|&i| rest & (1 << i) != 0) {
+                    for (rest_rank, rest_chain) in chains[rest * n + second].iter().enumerate() {
+                        let chain = Chain {
+                            score: pair_scores[first * n + second] + rest_chain.score,
+                            second: second as u8,
+                            rest_rank: rest_rank as u8,
+                        };
+                        if !insert_k_best(&mut best, k, chain, |c| c.score) {
+                            // Next chains of `second` have lower scores
+                            break;
+                        }
+                    }
+                }
+                chains[set * n + first] = best;
+            }
+        }
+    }
+
+    /* (prefix, p, rank of the chain following the prefix, score) */
+    let mut best: Vec<(Vec<usize>, usize, usize, f32)> = Vec::with_capacity(k + 1);
+    // Words often share their POS tags, so do prefixes
+    let mut n_gram_factors: FxHashMap<PosTagNGram, f32> = FxHashMap::default();
+    for prefix in (0..n).permutations(N_GRAM_PREFIX_SIZE) {
+        let mut prefix_score = 0.0;
+        for window in prefix.windows(2) {
+            prefix_score += pair_scores[window[0] * n + window[1]];
+        }
+        let tags = prefix.iter().map(|&i| index.word_pos(words[i])).collect_vec();
+        let n_gram_factor = *n_gram_factors
+            .entry(PosTagNGram::new(&tags))
+            .or_insert_with(|| N_GRAM_SCALE * index.pos_n_grams.score(&tags));
+        let last = *prefix.last().unwrap();
+        let remaining = prefix.iter().fold(nb_sets - 1, |set, &i| set & !(1 << i)) | (1 << last);
+        for (p, chains) in chains.iter().enumerate() {
+            for (rank, chain) in chains[remaining * n + last].iter().enumerate() {
+                let mut score = prefix_score + chain.score;
+                if p == 1 {
+                    score /= 4.0;
+                }
+                score *= n_gram_factor;
+                if !insert_k_best(&mut best, k, (prefix.clone(), p, rank, score), |o| o.3) {
+                    break;
+                }
+            }
+        }
+    }
+
+    best.into_iter()
+        .map(|(mut order, p, mut rank, _)| {
+            let mut set = order.iter().fold(nb_sets - 1, |set, &i| set & !(1 << i)) | (1 << order.last().unwrap());
+            let mut current = *order.last().unwrap();
+            while set != 1 << current {
+                let chain = chains[p][set * n + current][rank];
+                set &= !(1 << current);
+                current = chain.second as usize;
+                rank = chain.rest_rank as usize;
+                order.push(current);
+            }
+            // Score again from left to right, chains were summed in another order which can change the last digits
+            let score = prefix_score_ordering(&order.iter().map(|&i| words[i]).collect_vec(), index);
+            (order, score)
+        })
+        .collect()
+}
+
+/**
+ * Score of words in this order, read with these readings: sum of pair scores, penalized by the last word, times the
+ * n-gram factor and the probability of the readings
+ */
+fn score_ordering(ordered_words: &[WordId], readings: &[ReadingId], index: &Index) -> f32 {
+    score_combination(ordered_words, readings, index)
+        * n_gram_factor(readings, index)
+        * readings_probability(ordered_words, readings, index)
+}
+
+/**
+ * What `best_orderings` maximizes: `score_ordering` with the best readings of each pair of words, and the n-gram
+ * factor of the main readings of the first words only. It is the same for the words having a single reading.
+ * The best readings of a pair ignore their probability, the pair scores being computed per reading set, shared by
+ * words whose readings have other probabilities: an unlikely reading fitting a pair well ranks an ordering higher
+ * than `HeuristicScorer::score` does, until the rerank.
+ */
+fn prefix_score_ordering(ordered_words: &[WordId], index: &Index) -> f32 {
+    let prefix_size = ordered_words.len().min(N_GRAM_PREFIX_SIZE);
+    let mut score = 0.0;
+    for window in ordered_words.windows(2) {
+        score += pair_score(index, window[0], window[1]);
+    }
+    if is_penalized_last_word(index.word_pos(*ordered_words.last().unwrap())) {
+        score /= 4.0;
+    }
+    let main_readings = ordered_words[..prefix_size].iter().map(|&w| index.word_readings(w)[0]).collect_vec();
+    score * n_gram_factor(&main_readings, index)
+}
+
+fn score_combination(ordered_words: &[WordId], readings: &[ReadingId], index: &Index) -> f32 {
+    let mut score = 0.0;
+    for i in 1..ordered_words.len() {
+        score += pair_score_of_readings(index, (ordered_words[i - 1], ordered_words[i]), (readings[i - 1], readings[i]));
+    }
+    /*  If last word is ADP, DET, PRON, VERB penalize current combination */
+    if is_penalized_last_word(index.reading_pos(*readings.last().unwrap())) {
+        score /= 4.0;
+    }
+    score
+}
+
+fn is_penalized_last_word(pos: PosTag) -> bool {
+    pos == PosTag::ADP || pos == PosTag::DET || pos == PosTag::PRON || pos == PosTag::VERB
+}
+
+/** Small words are the ones with at most this number of letters */
+pub(crate) const SMALL_WORD_SIZE: usize = 4;
+/**
+ * Divides the score of every ordering, whatever its number of words: the square of the capacity of `Matching::matched`
+ * when it was 6 words, kept when it grew so that the scores and their calibration did not change
+ */
+const LENGTH_NORMALISATION: f32 = 36.0;
+
+/** Divisors applied to the score of every ordering of `words`: normalisation by the number of words, and small words penalty */
+fn expression_divisors(words: &[WordId], index: &Index) -> (f32, f32) {
+    let nb_small_words = words.iter()
+        .filter(|&&word| index.word_defs.nb_letters(word) <= SMALL_WORD_SIZE)
+        .count();
+    /* Penalize expression with lots of small words */
+    let small_words_penalty = (1.0 + nb_small_words as f32).powf(1.5);
+    (LENGTH_NORMALISATION, small_words_penalty)
+}
+
+#[derive(Serialize)]
+struct PairScore {
+    first: String,
+    second: String,
+    /** Occurences of the pair of words in the corpus, if it was seen */
+    word_bigram: Option<f32>,
+    score: f32,
+}
+
+/** Every component of the score of an ordering, in the order they are applied */
+#[derive(Serialize)]
+pub(crate) struct ScoreBreakdown {
+    /** Best tagging stat of each pair of consecutive words, with their occurences as a pair when seen */
+    pairs: Vec<PairScore>,
+    /** Multiplies the score: probability of the readings picked for the words, relative to their main readings */
+    readings_probability: f32,
+    /** Divides the sum of the pair scores when the last word is an ADP, DET, PRON or VERB */
+    last_word_penalty: f32,
+    /** POS tags of the readings picked for the words, see `best_readings` */
+    n_gram: Vec<PosTag>,
+    /** Probability of each POS tag given the previous ones, see `PosNGramModel::sliding_probabilities` */
+    n_gram_probabilities: Vec<f32>,
+    /** Multiplies the score: geometric mean of the probabilities, scaled */
+    n_gram_factor: f32,
+    length_normalisation: f32,
+    small_words_penalty: f32,
+    agreement_violations: Vec<Violation>,
+    /** Multiplies the score, once per agreement violation */
+    agreement_factor: f32,
+    /** Score given by the scorer */
+    scorer_score: f32,
+    /** Multiplies the score of the scorer, for what depends on the query, see `ScoreBreakdown::with_query_factor` */
+    query_factor: f32,
+    /** Score of the anagram, as returned */
+    pub(crate) score: f32,
+}
+
+impl ScoreBreakdown {
+    /**
+     * The breakdown with the factor applied by `Index::find_anagrams_reverse` to the score of any scorer: reuse of the
+     * input words, meaning, commonness of the words and accents, each of them detailed in `AnagramDetails`
+     */
+    pub(crate) fn with_query_factor(self, query_factor: f32) -> ScoreBreakdown {
+        ScoreBreakdown { query_factor, score: self.scorer_score * query_factor, ..self }
+    }
+}
+
+/** Breakdown of the score given to these words in this order by `HeuristicScorer` */
+fn explain_score(ordered_words: &[WordId], index: &Index) -> ScoreBreakdown {
+    let word_str = |word: WordId| u8_to_str(index.word_original_letters(word));
+    let mut breakdown = ScoreBreakdown {
+        pairs: vec![],
+        readings_probability: 1.0,
+        last_word_penalty: 1.0,
+        n_gram: vec![],
+        n_gram_probabilities: vec![],
+        n_gram_factor: 1.0,
+        length_normalisation: 1.0,
+        small_words_penalty: 1.0,
+        agreement_violations: vec![],
+        agreement_factor: 1.0,
+        scorer_score: index.score_calibration(HeuristicScorer.name()).single_word_score(),
+        query_factor: 1.0,
+        score: index.score_calibration(HeuristicScorer.name()).single_word_score(),
+    };
+    if ordered_words.len() == 1 {
+        return breakdown;
+    }
+    let readings = best_readings(ordered_words, index);
+    breakdown.pairs = (1..ordered_words.len())
+        .map(|i| PairScore {
+            first: word_str(ordered_words[i - 1]),
+            second: word_str(ordered_words[i]),
+            word_bigram: index.word_bigram_score(ordered_words[i - 1], ordered_words[i]),
+            score: pair_score_of_readings(index, (ordered_words[i - 1], ordered_words[i]), (readings[i - 1], readings[i])),
+        })
+        .collect();
+    breakdown.readings_probability = readings_probability(ordered_words, &readings, index);
+    if is_penalized_last_word(index.reading_pos(*readings.last().unwrap())) {
+        breakdown.last_word_penalty = 4.0;
+    }
+    breakdown.n_gram = readings.iter().map(|&r| index.reading_pos(r)).collect();
+    breakdown.n_gram_probabilities = index.pos_n_grams.sliding_probabilities(&breakdown.n_gram);
+    breakdown.n_gram_factor = n_gram_factor(&readings, index);
+    (breakdown.length_normalisation, breakdown.small_words_penalty) = expression_divisors(ordered_words, index);
+    breakdown.agreement_violations = agreement::violations(&readings, index);
+    breakdown.agreement_factor = agreement_factor(&breakdown.agreement_violations);
+    breakdown.scorer_score = score_ordering(ordered_words, &readings, index)
+        / breakdown.length_normalisation
+        / breakdown.small_words_penalty
+        * breakdown.agreement_factor;
+    breakdown.score = breakdown.scorer_score;
+    breakdown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{test_index, word_id};
+    use crate::{words_to_string, Gender, Morph, Number, Person};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /** Words of the test index, with random stats between their readings */
+    /** `index` with random reading pair stats and POS n-grams */
+    fn random_stats_index(mut index: Index, rng: &mut StdRng) -> Index {
+        for stat in index.reading_pair_stats.iter_mut() {
+            *stat = if rng.gen_bool(0.2) { 0.0 } else { rng.gen_range(1.0..100.0) };
+        }
+        index.pair_scores = PairScores::new(&index);
+        let pos = [PosTag::PRON, PosTag::DET, PosTag::VERB, PosTag::NOUN, PosTag::ADJ];
+        for size in 2..=4 {
+            for n_gram in std::iter::repeat_n(pos, size).multi_cartesian_product() {
+                if rng.gen_bool(0.5) {
+                    index.pos_n_grams.insert(&n_gram, rng.gen_range(1.0..50.0));
+                }
+            }
+        }
+        index.pos_n_grams.finalize();
+        index
+    }
+
+    #[test]
+    fn best_orderings_are_the_best_permutations() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for nb_words in 2..=EXHAUSTIVE_ORDERING_SIZE {
+            for _ in 0..20 {
+                let index = random_stats_index(test_index(), &mut rng);
+                let words: Vec<WordId> = (0..nb_words).map(|_| rng.gen_range(0..index.word_defs.len() as WordId)).collect();
+                let orderings = HeuristicScorer.best_orderings(&words, &index, 3);
+                // Stable, so that the first permutation wins on equal scores
+                let permutations: Vec<(Vec<usize>, f32)> = (0..nb_words)
+                    .permutations(nb_words)
+                    .map(|perm| {
+                        let score = HeuristicScorer.score(&perm.iter().map(|&i| words[i]).collect_vec(), &index);
+                        (perm, score)
+                    })
+                    .sorted_by(|a, b| b.1.partial_cmp(&a.1).unwrap())
+                    .take(3)
+                    .collect();
+                assert_eq!(orderings, permutations);
+            }
+        }
+    }
+
+    #[test]
+    fn orderings_of_homographs_are_the_best_permutations() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut index = test_index();
+        let masc_sing = Morph { gender: Some(Gender::Masc), number: Some(Number::Sing), ..Morph::default() };
+        let third_sing = Morph { number: Some(Number::Sing), person: Some(Person::Three), ..Morph::default() };
+        index.push_homographs(
+            "est",
+            vec![(PosTag::NOUN, vec![masc_sing], Some(0.2)), (PosTag::VERB, vec![third_sing], Some(0.8))],
+            0.0,
+        );
+        index.push_homographs(
+            "sel",
+            vec![(PosTag::NOUN, vec![masc_sing], None), (PosTag::ADJ, vec![masc_sing], None)],
+            0.0,
+        );
+        index.finalize();
+        let homographs = [word_id(&index, "est"), word_id(&index, "sel")];
+        for nb_words in 2..=EXHAUSTIVE_ORDERING_SIZE {
+            for _ in 0..10 {
+                let index = random_stats_index(index.clone(), &mut rng);
+                let mut words: Vec<WordId> = (2..nb_words).map(|_| rng.gen_range(0..index.word_defs.len() as WordId)).collect();
+                words.extend(homographs);
+                let orderings = HeuristicScorer.best_orderings(&words, &index, 3);
+                let permutations: Vec<(Vec<usize>, f32)> = (0..nb_words)
+                    .permutations(nb_words)
+                    .map(|perm| {
+                        let score = HeuristicScorer.score(&perm.iter().map(|&i| words[i]).collect_vec(), &index);
+                        (perm, score)
+                    })
+                    .sorted_by(|a, b| b.1.partial_cmp(&a.1).unwrap())
+                    .take(3)
+                    .collect();
+                assert_eq!(orderings, permutations);
+            }
+        }
+    }
+
+    #[test]
+    fn kept_orderings_are_the_best_kept_permutations() {
+        let mut rng = StdRng::seed_from_u64(17);
+        for nb_words in [5, 8] {
+            let index = random_stats_index(test_index(), &mut rng);
+            let words: Vec<WordId> = (0..nb_words).map(|_| rng.gen_range(0..index.word_defs.len() as WordId)).collect();
+            /* Orderings ending with a given word, not one with a penalty there */
+            let last = *words.iter().find(|&&w| !is_penalized_last_word(index.word_pos(w))).unwrap();
+            let keep = |ordered: &[WordId]| ordered[nb_words - 1] == last;
+            let orderings = HeuristicScorer.best_kept_orderings(&words, &index, 3, &keep);
+            assert_eq!(orderings.len(), 3);
+            assert!(orderings.iter().all(|(perm, _)| keep(&perm.iter().map(|&i| words[i]).collect_vec())));
+            if nb_words <= EXHAUSTIVE_ORDERING_SIZE {
+                let permutations: Vec<(Vec<usize>, f32)> = (0..nb_words)
+                    .permutations(nb_words)
+                    .filter_map(|perm| {
+                        let ordered = perm.iter().map(|&i| words[i]).collect_vec();
+                        keep(&ordered).then(|| (perm, HeuristicScorer.score(&ordered, &index)))
+                    })
+                    .sorted_by(|a, b| b.1.partial_cmp(&a.1).unwrap())
+                    .take(3)
+                    .collect();
+                assert_eq!(orderings, permutations);
+            }
+        }
+    }
+
+    #[test]
+    fn dynamic_programming_finds_the_best_prefix_scores() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for nb_words in 2..=7 {
+            for _ in 0..20 {
+                let index = random_stats_index(test_index(), &mut rng);
+                let words: Vec<WordId> = (0..nb_words).map(|_| rng.gen_range(0..index.word_defs.len() as WordId)).collect();
+                let orderings = best_orderings(&words, &index, 3);
+                let permutation_scores: Vec<f32> = words.iter()
+                    .permutations(nb_words)
+                    .map(|perm| prefix_score_ordering(&perm.into_iter().cloned().collect_vec(), &index))
+                    .sorted_by(|a, b| b.partial_cmp(a).unwrap())
+                    .collect();
+                assert_eq!(orderings.len(), 3.min(permutation_scores.len()));
+                assert!(orderings.iter().map(|o| &o.0).all_unique());
+                for ((order, score), permutation_score) in orderings.iter().zip(permutation_scores) {
+                    assert_eq!(order.iter().sorted().cloned().collect_vec(), (0..nb_words).collect_vec());
+                    let ordered: Vec<WordId> = order.iter().map(|&i| words[i]).collect();
+                    assert_eq!(prefix_score_ordering(&ordered, &index), *score);
+                    assert!((permutation_score - score).abs() <= score * 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn best_ordering_of_long_expressions() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let index = random_stats_index(test_index(), &mut rng);
+        let words: Vec<WordId> = (0..10).map(|_| rng.gen_range(0..index.word_defs.len() as WordId)).collect();
+        let (order, score) = best_orderings(&words, &index, 5).remove(0);
+        let ordered: Vec<WordId> = order.iter().map(|&i| words[i]).collect();
+        assert!((prefix_score_ordering(&ordered, &index) - score).abs() <= score * 1e-5);
+    }
+
+    #[test]
+    fn prefix_score_misses_the_best_ordering() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let index = random_stats_index(test_index(), &mut rng);
+        let words: Vec<WordId> = (0..6).map(|_| rng.gen_range(0..index.word_defs.len() as WordId)).collect();
+        let (prefix_best, _) = best_orderings(&words, &index, 1).remove(0);
+        let (best, score) = HeuristicScorer.best_orderings(&words, &index, 1).remove(0);
+        /* The n-gram after the first words and the agreement make another ordering better */
+        assert_ne!(prefix_best, best);
+        assert!(HeuristicScorer.score(&prefix_best.iter().map(|&i| words[i]).collect_vec(), &index) < score);
+    }
+
+    #[test]
+    fn pair_scores_cover_every_pair() {
+        let mut index = test_index();
+        let noun = Morph { gender: Some(Gender::Masc), number: Some(Number::Sing), ..Morph::default() };
+        let verb = Morph { number: Some(Number::Sing), person: Some(Person::Three), ..Morph::default() };
+        index.push_homographs("est", vec![(PosTag::NOUN, vec![noun], None), (PosTag::VERB, vec![verb], None)], 0.0);
+        index.finalize();
+        let (nb_readings, nb_reading_sets) = (index.readings.len(), index.reading_sets.len());
+        assert_eq!(index.reading_pair_stats.len(), nb_readings * nb_readings);
+        assert_eq!(index.pair_scores.readings.len(), nb_readings * nb_readings);
+        assert_eq!(index.pair_scores.reading_sets.len(), nb_reading_sets * nb_readings);
+        for (first, second) in (0..nb_reading_sets as ReadingSetId).cartesian_product(0..nb_reading_sets as ReadingSetId) {
+            let best = index.reading_sets[first as usize]
+                .iter()
+                .cartesian_product(&index.reading_sets[second as usize])
+                .map(|(&a, &b)| index.pair_scores.of_readings(a, b))
+                .fold(0.0, f32::max);
+            assert_eq!(index.pair_scores.of_reading_sets(first, &index.reading_sets[second as usize]), best);
+        }
+    }
+
+    #[test]
+    fn explained_score_is_the_score() {
+        let index = test_index();
+        let words = [word_id(&index, "nul"), word_id(&index, "lit"), word_id(&index, "le")];
+        let (best, score) = HeuristicScorer.best_orderings(&words, &index, 1).remove(0);
+        let best: Vec<WordId> = best.iter().map(|&i| words[i]).collect();
+        assert_eq!(words_to_string(&best, &index), "le lit nul");
+        assert_eq!(HeuristicScorer.score(&best, &index), score);
+        let breakdown = HeuristicScorer.explain(&best, &index).unwrap();
+        assert_eq!(breakdown.score, score);
+        assert_eq!(breakdown.pairs.iter().map(|p| p.score).collect_vec(), vec![100.0, 50.0]);
+        assert_eq!(breakdown.n_gram, vec![PosTag::DET, PosTag::NOUN, PosTag::ADJ]);
+        assert_eq!(breakdown.n_gram_probabilities.len(), 3);
+        assert_eq!(breakdown.n_gram_factor, N_GRAM_SCALE * index.pos_n_grams.score(&breakdown.n_gram));
+        assert_eq!(breakdown.last_word_penalty, 1.0);
+        assert_eq!(breakdown.small_words_penalty, 4.0_f32.powf(1.5));
+    }
+
+    #[test]
+    fn beam_search_orderings() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let index = random_stats_index(test_index(), &mut rng);
+        /* Relies on the default best_orderings */
+        struct NoDpScorer;
+        impl Scorer for NoDpScorer {
+            fn name(&self) -> &'static str {
+                "test"
+            }
+            fn score(&self, ordered_words: &[WordId], index: &Index) -> f32 {
+                HeuristicScorer.score(ordered_words, index)
+            }
+        }
+        for nb_words in [3, 6, 8] {
+            let words: Vec<WordId> = (0..nb_words).map(|_| rng.gen_range(0..index.word_defs.len() as WordId)).collect();
+            let orderings = NoDpScorer.best_orderings(&words, &index, 2);
+            assert_eq!(orderings.len(), 2);
+            assert!(orderings[0].1 >= orderings[1].1);
+            let best = HeuristicScorer.best_orderings(&words, &index, 1).remove(0);
+            if nb_words <= EXHAUSTIVE_ORDERING_SIZE {
+                // Both scored all the permutations
+                assert_eq!(orderings[0], best);
+            } else {
+                // Both are heuristics
+                let ordered = orderings[0].0.iter().map(|&i| words[i]).collect_vec();
+                assert_eq!(HeuristicScorer.score(&ordered, &index), orderings[0].1);
+                let ordered = best.0.iter().map(|&i| words[i]).collect_vec();
+                assert_eq!(HeuristicScorer.score(&ordered, &index), best.1);
+            }
+        }
+    }
+
+    #[test]
+    fn word_bigrams_rerank() {
+        let mut index = test_index();
+        let words = [word_id(&index, "lit"), word_id(&index, "nul")];
+        let best = HeuristicScorer.best_orderings(&words, &index, 1).remove(0).0;
+        assert_eq!(best, vec![0, 1]);
+        /* "nul lit" is a collocation, even if ADJ NOUN is less frequent than NOUN ADJ */
+        index.word_bigrams.insert((words[1], words[0]), 10.0);
+        let best = HeuristicScorer.best_orderings(&words, &index, 1).remove(0).0;
+        assert_eq!(best, vec![1, 0]);
+        assert_eq!(pair_score(&index, words[0], words[1]), index.reading_pair_score(words[0], words[1]));
+    }
+
+    #[test]
+    fn homographs_are_read_in_context() {
+        let mut index = test_index();
+        let masc_sing = Morph { gender: Some(Gender::Masc), number: Some(Number::Sing), ..Morph::default() };
+        let third_sing = Morph { number: Some(Number::Sing), person: Some(Person::Three), ..Morph::default() };
+        index.push_homographs(
+            "est",
+            vec![(PosTag::NOUN, vec![masc_sing], Some(0.2)), (PosTag::VERB, vec![third_sing], Some(0.8))],
+            0.0,
+        );
+        index.finalize();
+        let (il, le, est) = (word_id(&index, "il"), word_id(&index, "le"), word_id(&index, "est"));
+        let (noun, verb) = (index.word_readings(est)[1], index.word_readings(est)[0]);
+        assert_eq!(index.word_pos(est), PosTag::VERB);
+        assert_eq!(index.reading_probability(est, noun), 0.25);
+        assert_eq!(best_readings(&[il, est], &index)[1], verb);
+        /* DET NOUN is seen more than PRON VERB, enough for the less probable reading */
+        assert_eq!(best_readings(&[le, est], &index)[1], noun);
+        let breakdown = explain_score(&[le, est], &index);
+        assert_eq!(breakdown.n_gram, vec![PosTag::DET, PosTag::NOUN]);
+        assert_eq!(breakdown.readings_probability, 0.25);
+        assert_eq!(breakdown.score, HeuristicScorer.score(&[le, est], &index));
+    }
+
+    #[test]
+    fn leading_homographs_are_read_in_context() {
+        let mut index = test_index();
+        let masc_sing = Morph { gender: Some(Gender::Masc), number: Some(Number::Sing), ..Morph::default() };
+        let third_sing = Morph { number: Some(Number::Sing), person: Some(Person::Three), ..Morph::default() };
+        index.push_homographs(
+            "est",
+            vec![(PosTag::NOUN, vec![masc_sing], Some(0.2)), (PosTag::VERB, vec![third_sing], Some(0.8))],
+            0.0,
+        );
+        let others = ["bel", "bol", "cil", "col", "fil", "sol"];
+        for word in others {
+            index.push_homographs(
+                word,
+                vec![(PosTag::INTJ, vec![], Some(0.6)), (PosTag::NUM, vec![], Some(0.4))],
+                0.0,
+            );
+        }
+        index.finalize();
+        let est = word_id(&index, "est");
+        let ordered = [word_id(&index, "le"), est].into_iter().chain(others.map(|w| word_id(&index, w))).collect_vec();
+        /* More combinations than can be tried, the readings of the others not mattering */
+        assert!(2_usize.pow(7) > MAX_READING_COMBINATIONS);
+        assert_eq!(best_readings(&ordered, &index)[1], index.word_readings(est)[1]);
+        assert_eq!(index.reading_pos(index.word_readings(est)[1]), PosTag::NOUN);
+    }
+}
@@ -0,0 +1,92 @@
+/**
+ * Other orders than by score for browsing the results, applied once they are scored, deduplicated and diversified.
+ * Anagrams that compare equal keep their order by score. Grouped anagrams are sorted within their group, and groups
+ * come in the order of their first anagram.
+ */
+use crate::{Index, WordId};
+use itertools::Itertools;
+use rustc_hash::FxHashMap;
+use serde_derive::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SortBy {
+    #[default]
+    Score,
+    FewestWords,
+    /** The anagrams with the longest word first */
+    LongestWord,
+    /** By the words, as in a dictionary: the diacritics only matter between words with the same letters */
+    Alphabetical,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum GroupBy {
+    #[default]
+    None,
+    /** Anagrams sharing their main word, see `main_word` */
+    MainWord,
+}
+
+/** Longest word of the anagram, the first of them in the ordering on ties */
+pub(crate) fn main_word(words: &[WordId], index: &Index) -> WordId {
+    *words.iter().rev().max_by_key(|&&w| index.word_defs.nb_letters(w)).unwrap()
+}
+
+/** New order of `anagrams`, given as the words of their best ordering in the current order */
+pub(crate) fn sorted_order(anagrams: &[&[WordId]], index: &Index, sort_by: SortBy, group_by: GroupBy) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..anagrams.len()).collect();
+    match sort_by {
+        SortBy::Score => {}
+        SortBy::FewestWords => order.sort_by_key(|&i| anagrams[i].len()),
+        SortBy::LongestWord => {
+            order.sort_by_key(|&i| Reverse(anagrams[i].iter().map(|&w| index.word_defs.nb_letters(w)).max()))
+        }
+        SortBy::Alphabetical => order.sort_by_cached_key(|&i| {
+            let letters = anagrams[i].iter().map(|&w| index.word_original_letters(w)).collect_vec();
+            /* The diacritics are the last 3 bits of the encoded letters */
+            let base_letters = letters.iter().map(|l| l.iter().map(|c| c >> 3).collect_vec()).collect_vec();
+            (base_letters, letters)
+        }),
+    }
+    if group_by == GroupBy::MainWord {
+        let mut group_ranks: FxHashMap<WordId, usize> = FxHashMap::default();
+        for &i in &order {
+            let nb_groups = group_ranks.len();
+            group_ranks.entry(main_word(anagrams[i], index)).or_insert(nb_groups);
+        }
+        order.sort_by_key(|&i| group_ranks[&main_word(anagrams[i], index)]);
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{test_index, word_id};
+    use crate::PosTag;
+
+    #[test]
+    fn anagrams_are_sorted_then_grouped() {
+        let mut index = test_index();
+        index.push_word("éclat", PosTag::NOUN, vec![], 0.0);
+        index.push_word("ecluse", PosTag::NOUN, vec![], 0.0);
+        let [le, un, il, lit, eclat, ecluse] = ["le", "un", "il", "lit", "éclat", "ecluse"].map(|w| word_id(&index, w));
+        let first = [lit, le, un];
+        let second = [ecluse, il];
+        let third = [eclat, un];
+        let fourth = [ecluse, le, un];
+        let anagrams: Vec<&[WordId]> = vec![&first, &second, &third, &fourth];
+
+        assert_eq!(sorted_order(&anagrams, &index, SortBy::Score, GroupBy::None), vec![0, 1, 2, 3]);
+        assert_eq!(sorted_order(&anagrams, &index, SortBy::FewestWords, GroupBy::None), vec![1, 2, 0, 3]);
+        assert_eq!(sorted_order(&anagrams, &index, SortBy::LongestWord, GroupBy::None), vec![1, 3, 2, 0]);
+        /* "éclat" comes before "ecluse", as "eclat" would */
+        assert_eq!(sorted_order(&anagrams, &index, SortBy::Alphabetical, GroupBy::None), vec![2, 1, 3, 0]);
+        assert_eq!(sorted_order(&anagrams, &index, SortBy::Score, GroupBy::MainWord), vec![0, 1, 3, 2]);
+        assert_eq!(sorted_order(&anagrams, &index, SortBy::FewestWords, GroupBy::MainWord), vec![1, 3, 2, 0]);
+        assert_eq!(main_word(&[le, lit, un], &index), lit);
+    }
+}
@@ -83,7 +83,7 @@ lexique_gender_number_mapping = {
     'p': 'Plur'
 }
 
-lexique = pd.read_csv("Lexique383.tsv", sep="\t", usecols = ['ortho', 'cgram', 'genre', 'nombre', 'freqlemlivres', 'infover'])
+lexique = pd.read_csv("Lexique383.tsv", sep="\t", usecols = ['ortho', 'lemme', 'cgram', 'genre', 'nombre', 'freqlemlivres', 'infover', 'freqfilms2', 'freqlivres'])
 lexique = lexique[lexique['freqlemlivres'] > 2]
 lexique['tagging'] = lexique.apply(lexique_row_to_pos_morph_tuple, axis=1)
 vocab = {w: defaultdict(int) for w in set(lexique['ortho'])}
@@ -92,6 +92,15 @@ for word in blacklist:
         del vocab[word]
 
 static_pos_tag = lexique.groupby('ortho').agg(list).reset_index().set_index('ortho').to_dict('index')
+# Occurences per million words of each form, summed over its lemmas and averaged over films and books
+frequencies = lexique.groupby('ortho')[['freqfilms2', 'freqlivres']].sum().mean(axis=1).to_dict()
+# Lemmas of each form, the most frequent first, e.g. "suis" is both "être" and "suivre"
+lemmas = (
+    lexique.sort_values('freqlemlivres', ascending=False)
+    .groupby('ortho')['lemme']
+    .agg(lambda l: [x for x in dict.fromkeys(l) if isinstance(x, str)])
+    .to_dict()
+)
 del lexique
     
 encountered_vocab = set()
@@ -124,10 +133,13 @@ def keep_interesting_morph(input_morph):
     return frozendict(out_morph)
 
 
+# Kept in the vocab only: the tagging stats are keyed by the Morph features above
+verb_morph_keys = ["VerbForm", "Tense", "Mood", "Definite"]
+
+
 def keep_interesting_morph_dict(input_morph):
     out_morph = {}
-    for morph in list(Morph):
-        key = morph.name
+    for key in [morph.name for morph in list(Morph)] + verb_morph_keys:
         m = input_morph.get(key)
         if m is not None:
             out_morph[key] = m
@@ -160,6 +172,7 @@ def print_doc(doc):
 
 
 pos_n_grams = defaultdict(int)
+max_n_gram_size = 6
 batch_size = 1000
 for doc_path in doc_paths:
     processed = 0
@@ -209,20 +222,11 @@ for doc_path in doc_paths:
                         next_token_pos,
                         keep_interesting_morph(next_token.morph),
                     )
-                    pos_n_grams[(token_pos, next_token_pos)] += 1
-                    if index < len(doc) - 2:
-                        pos_n_grams[
-                            (token_pos, next_token_pos, doc[index + 2].pos_)
-                        ] += 1
-                    if index < len(doc) - 3:
-                        pos_n_grams[
-                            (
-                                token_pos,
-                                next_token_pos,
-                                doc[index + 2].pos_,
-                                doc[index + 3].pos_,
-                            )
-                        ] += 1
+                    # Up to the maximum number of words of an expression, see MAX_EXPR_SIZE in the engine
+                    for n in range(2, max_n_gram_size + 1):
+                        if index > len(doc) - n:
+                            break
+                        pos_n_grams[tuple(t.pos_ for t in doc[index : index + n])] += 1
                     mappings[tup] += 1
 
 
@@ -257,19 +261,39 @@ with open("tagging_stats.jsonl", "w") as f:
 
 final_vocab = []
 
+# A word gets one reading per POS tag it was seen with, the most probable first. Tags seen less often than this are
+# left out, as the tagger errors
+min_reading_prob = 0.1
+max_readings = 3
+
+
+def to_readings(morphs_by_pos, occ_by_pos):
+    total = sum(occ_by_pos.values())
+    readings = []
+    for pos, occ in sorted(occ_by_pos.items(), key=lambda x: x[1], reverse=True)[:max_readings]:
+        prob = occ / total if total > 0 else 1.0
+        if readings and prob < min_reading_prob:
+            break
+        readings.append({"pos": pos, "morph": morphs_by_pos[pos], "prob": round(prob, 3)})
+    return readings
+
+
 remaining_words = set(vocab.keys()) - encountered_vocab
 
 print(f"{len(remaining_words)} remaining words in vocab")
 for index, word in tqdm(enumerate(remaining_words)):
     if word not in static_pos_tag:
         continue
-    final_vocab.append(
-        {
-            "word": word,
-            "pos": static_pos_tag[word]['tagging'][0][0],
-            "morph": tuple(map(lambda x: x[1], static_pos_tag[word]['tagging']))
-        }
-    )
+    lexique_rows = static_pos_tag[word]
+    morphs_by_pos = defaultdict(list)
+    occ_by_pos = defaultdict(float)
+    for (pos, morph), freq_films, freq_books in zip(
+        lexique_rows['tagging'], lexique_rows['freqfilms2'], lexique_rows['freqlivres']
+    ):
+        if dict(morph) not in morphs_by_pos[pos]:
+            morphs_by_pos[pos].append(dict(morph))
+        occ_by_pos[pos] += freq_films + freq_books
+    final_vocab.append({"word": word, "readings": to_readings(morphs_by_pos, occ_by_pos)})
 
 with open("stats_final_remaining.txt", "w") as f:
     for v in final_vocab:
@@ -284,32 +308,36 @@ with open("dump_vocab.txt", "w") as f:
 for word in encountered_vocab:
     stats = vocab[word]
     if len(stats) == 0:
-        final_vocab.append({"word": word, "pos": "", "morph": {}})
+        final_vocab.append({"word": word, "readings": []})
         continue
     sorted_by_occ = sorted(stats.items(), key=lambda x: x[1], reverse=True)
-    first_pos_morph = sorted_by_occ[0][0]
-    morph_to_keep = [keep_interesting_morph_dict(first_pos_morph[1])]
-    for (pos, morph), occ in sorted_by_occ[1:]:
-        if pos == first_pos_morph[0] and occ > 10:
-            to_add = keep_interesting_morph_dict(morph)
-            should_add = True
-            for existing in morph_to_keep:
-                if existing == to_add:
-                    should_add = False
-                    continue
-                biggest = dict_intersect(existing, to_add)
-                if biggest is not None:
-                    should_add = False
-                    existing.update(biggest)
-                    continue
-            if should_add:
-                morph_to_keep.append(to_add)
-    for m in morph_to_keep:
-        gender = m.get('Gender')
-        if gender is not None and first_pos_morph[0] == "VERB":
-            del m['Gender']
-    to_add = {"word": word, "pos": first_pos_morph[0], "morph": morph_to_keep}
-    final_vocab.append(to_add)
+    morphs_by_pos = {}
+    occ_by_pos = defaultdict(int)
+    for (pos, morph), occ in sorted_by_occ:
+        occ_by_pos[pos] += occ
+        to_add = keep_interesting_morph_dict(morph)
+        if pos == "VERB":
+            to_add.pop('Gender', None)
+        # The most frequent morphology of each POS, and the other frequent enough ones
+        if pos not in morphs_by_pos:
+            morphs_by_pos[pos] = [to_add]
+            continue
+        if occ <= 10:
+            continue
+        morph_to_keep = morphs_by_pos[pos]
+        should_add = True
+        for existing in morph_to_keep:
+            if existing == to_add:
+                should_add = False
+                continue
+            biggest = dict_intersect(existing, to_add)
+            if biggest is not None:
+                should_add = False
+                existing.update(biggest)
+                continue
+        if should_add:
+            morph_to_keep.append(to_add)
+    final_vocab.append({"word": word, "readings": to_readings(morphs_by_pos, occ_by_pos)})
 
 alpha_diacritic_regex = re.compile(r"[^A-Za-z_À-ÿ]")
 
@@ -322,12 +350,11 @@ def should_keep_word(vocab_item):
 
 
 def _should_keep_word(vocab_item):
-    # filter out PROPN and X and unidentified
-    if (
-        vocab_item["pos"] == "PROPN"
-        or vocab_item["pos"] == "X"
-        or vocab_item["pos"] == ""
-    ):
+    # filter out PROPN and X readings, and the words left without any
+    vocab_item["readings"] = [
+        r for r in vocab_item["readings"] if r["pos"] != "PROPN" and r["pos"] != "X"
+    ]
+    if len(vocab_item["readings"]) == 0:
         return False
     word = vocab_item["word"]
     if len(word) < 2:
@@ -337,7 +364,14 @@ def _should_keep_word(vocab_item):
     return True
 
 
-final_vocab = filter(should_keep_word, final_vocab)
+final_vocab = list(filter(should_keep_word, final_vocab))
+# Used by the engine to favour common words, 0 for the words missing from Lexique
+for vocab_item in final_vocab:
+    vocab_item["freq"] = round(frequencies.get(vocab_item["word"], 0.0), 2)
+# Used by the engine to include or exclude all the forms of a lemma, the words missing from Lexique are their own
+for vocab_item in final_vocab:
+    word_lemmas = lemmas.get(vocab_item["word"]) or [vocab_item["word"]]
+    vocab_item["lemma"] = word_lemmas[0] if len(word_lemmas) == 1 else word_lemmas
 final_vocab = sorted(
     final_vocab,
     key=lambda x: (
@@ -348,7 +382,12 @@ final_vocab = sorted(
 write_jsonl("vocab.jsonl", final_vocab)
 
 
-with open("2-grams.txt", "w") as f:
-    for k, v in two_grams.items():
+# Used by the engine to score pairs of words, see WORD_BIGRAMS_PATH, written where it reads it
+final_words = set(x["word"] for x in final_vocab)
+with open("../engine/data/word_bigrams.jsonl", "w") as f:
+    for k, v in sorted(two_grams.items(), key=lambda x: x[1], reverse=True):
+        # Pairs seen once are mostly noise
+        if v < 2 or k[0] not in final_words or k[1] not in final_words:
+            continue
         l = {'2-gram': list(k), 'occ': v}
         f.write(orjson.dumps(l).decode() + '\n')
\ No newline at end of file
@@ -0,0 +1,22 @@
+# Keep only the vectors of our vocabulary from a fastText or word2vec text file, e.g.
+# https://dl.fbaipublicfiles.com/fasttext/vectors-crawl/cc.fr.300.vec.gz
+# python export_embeddings.py cc.fr.300.vec ../engine/data/embeddings.vec
+import sys
+import orjson
+from tqdm import tqdm
+
+vectors_path, out_path = sys.argv[1], sys.argv[2]
+
+with open("words.jsonl") as f:
+    vocab = set(orjson.loads(line)["word"] for line in f)
+
+kept = 0
+with open(vectors_path) as file_in, open(out_path, "w") as file_out:
+    for line in tqdm(file_in):
+        word = line.split(" ", 1)[0]
+        if word in vocab:
+            file_out.write(line)
+            kept += 1
+            vocab.remove(word)
+
+print(f"{kept} vectors kept, {len(vocab)} words without vector")